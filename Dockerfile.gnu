@@ -15,10 +15,17 @@ RUN cargo build --release --bin moco
 
 FROM debian:bookworm-slim
 
-RUN adduser --disabled-password app
+RUN adduser --disabled-password app \
+    && mkdir /data \
+    && chown app:app /data
 USER app
 
 WORKDIR /app
 
+# The state journal is written to /data. Mount a volume there to remember the followed and pruned
+# communities across restarts.
+ENV STATE_FILE=/data/moco.state.jsonl
+VOLUME /data
+
 COPY --from=builder /app/target/release/moco /usr/local/bin
 ENTRYPOINT ["/usr/local/bin/moco"]
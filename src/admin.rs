@@ -0,0 +1,206 @@
+use crate::{
+    api::SortType,
+    cli,
+    populater::{self, Command, Outcome, Source},
+    server,
+    supervisor::{Filter, Supervisor, TaskInfo},
+};
+use clap::ValueEnum;
+use hyper::{Body, Method, StatusCode};
+use serde::Serialize;
+use std::{future::Future, net::SocketAddr};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::error;
+
+/// A request from the admin API that must be handled by the supervisor
+pub enum Request {
+    /// Describe the matching tasks
+    Tasks(Filter, oneshot::Sender<Vec<TaskInfo>>),
+    /// Send a command to the matching tasks
+    Command(Filter, Command, oneshot::Sender<Vec<TaskInfo>>),
+    /// Follow a community by its `name@instance`
+    Follow(String, oneshot::Sender<Result<Followed, String>>),
+}
+
+/// The result of manually following a community
+#[derive(Debug, Serialize)]
+pub struct Followed {
+    community: String,
+    followed: bool,
+    reason: Option<&'static str>,
+}
+
+/// Bind to the address, returning a future that serves the admin API until stopped
+///
+/// Requests are forwarded to the supervisor through the channel.
+///
+/// - `GET /tasks` lists the running populaters
+/// - `POST /tasks/run`, `/tasks/pause`, and `/tasks/resume` control the populaters
+/// - `POST /follow?community=<[!]name@instance>` follows a community
+///
+/// The task endpoints accept `peer`, `source`, and `sort` query parameters to select a subset of
+/// the populaters.
+pub fn serve(
+    address: SocketAddr,
+    requests: mpsc::Sender<Request>,
+    stop: broadcast::Receiver<()>,
+) -> Result<impl Future<Output = ()>, hyper::Error> {
+    server::serve("admin api", address, stop, move |request| {
+        let requests = requests.clone();
+        async move {
+            let method = request.method().clone();
+            let path = request.uri().path().to_owned();
+            let query = request.uri().query().unwrap_or_default().to_owned();
+
+            match route(&method, &path, &query, &requests).await {
+                Ok(response) => response,
+                Err((status, message)) => server::text(status, &message),
+            }
+        }
+    })
+}
+
+/// Route the request to its handler
+async fn route(
+    method: &Method,
+    path: &str,
+    query: &str,
+    requests: &mpsc::Sender<Request>,
+) -> Result<hyper::Response<Body>, (StatusCode, String)> {
+    let command = match (method, path) {
+        (&Method::GET, "/tasks") => {
+            let filter = parse_filter(query)?;
+            let tasks = forward(requests, |reply| Request::Tasks(filter, reply)).await?;
+            return Ok(server::json(StatusCode::OK, &tasks));
+        }
+        (&Method::POST, "/follow") => {
+            let community = parameters(query)
+                .find(|(key, _)| key == "community")
+                .map(|(_, value)| value)
+                .ok_or_else(|| bad_request("missing community parameter"))?;
+            let community = cli::parse_community(&community).map_err(|e| bad_request(&e))?;
+
+            let result = forward(requests, |reply| Request::Follow(community, reply)).await?;
+            return match result {
+                Ok(followed) => Ok(server::json(StatusCode::OK, &followed)),
+                Err(message) => Err((StatusCode::BAD_GATEWAY, message)),
+            };
+        }
+        (&Method::POST, "/tasks/run") => Command::Run,
+        (&Method::POST, "/tasks/pause") => Command::Pause,
+        (&Method::POST, "/tasks/resume") => Command::Resume,
+        (_, "/tasks" | "/follow" | "/tasks/run" | "/tasks/pause" | "/tasks/resume") => {
+            return Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method not allowed".to_owned(),
+            ));
+        }
+        _ => return Err((StatusCode::NOT_FOUND, "not found".to_owned())),
+    };
+
+    let filter = parse_filter(query)?;
+    let tasks = forward(requests, |reply| Request::Command(filter, command, reply)).await?;
+    Ok(server::json(StatusCode::OK, &tasks))
+}
+
+/// Send the request to the supervisor and wait for its reply
+async fn forward<T>(
+    requests: &mpsc::Sender<Request>,
+    request: impl FnOnce(oneshot::Sender<T>) -> Request,
+) -> Result<T, (StatusCode, String)> {
+    let unavailable = || (StatusCode::SERVICE_UNAVAILABLE, "shutting down".to_owned());
+
+    let (reply, response) = oneshot::channel();
+    requests
+        .send(request(reply))
+        .await
+        .map_err(|_| unavailable())?;
+    response.await.map_err(|_| unavailable())
+}
+
+/// Parse the task selection from the query parameters
+fn parse_filter(query: &str) -> Result<Filter, (StatusCode, String)> {
+    let mut filter = Filter::default();
+    for (key, value) in parameters(query) {
+        match key.as_str() {
+            "peer" => filter.peer = Some(value),
+            "source" => {
+                let source = Source::from_str(&value, true)
+                    .map_err(|_| bad_request(&format!("unknown source {value:?}")))?;
+                filter.source = Some(source);
+            }
+            "sort" => {
+                let sort = SortType::from_str(&value, true)
+                    .map_err(|_| bad_request(&format!("unknown sort method {value:?}")))?;
+                filter.sort = Some(sort);
+            }
+            _ => return Err(bad_request(&format!("unknown parameter {key:?}"))),
+        }
+    }
+
+    Ok(filter)
+}
+
+/// Decode the query parameters
+fn parameters(query: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    url::form_urlencoded::parse(query.as_bytes()).map(|(k, v)| (k.into_owned(), v.into_owned()))
+}
+
+fn bad_request(message: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.to_owned())
+}
+
+/// Handle a request from the admin API
+///
+/// Manual follows run in the background so that slow instances don't hold up the supervisor.
+pub fn respond(request: Request, supervisor: &Supervisor) {
+    match request {
+        Request::Tasks(filter, reply) => {
+            let _ = reply.send(supervisor.tasks(&filter));
+        }
+        Request::Command(filter, command, reply) => {
+            let _ = reply.send(supervisor.command(&filter, command));
+        }
+        Request::Follow(community, reply) => {
+            let Some((_, instance)) = community.split_once('@') else {
+                let _ = reply.send(Err(format!(
+                    "invalid community {community:?}, expected name@instance"
+                )));
+                return;
+            };
+
+            let follower = supervisor.follower(instance);
+            tokio::task::spawn(async move {
+                let context = match follower.context().await {
+                    Ok(context) => context,
+                    Err(error) => {
+                        let _ = reply.send(Err(format!("{error:#}")));
+                        return;
+                    }
+                };
+
+                let result = match populater::follow(&context, &community).await {
+                    Ok(outcome) => Ok(Followed {
+                        followed: matches!(outcome, Outcome::Followed),
+                        reason: match outcome {
+                            Outcome::Followed => None,
+                            Outcome::Planned => Some("dry run"),
+                            Outcome::Skipped(reason) => Some(reason),
+                        },
+                        community,
+                    }),
+                    Err(error) => {
+                        error!(
+                            %community,
+                            error = &error as &(dyn std::error::Error + 'static),
+                            "failed to follow community"
+                        );
+                        Err(error.to_string())
+                    }
+                };
+
+                let _ = reply.send(result);
+            });
+        }
+    }
+}
@@ -1,29 +1,54 @@
+use crate::metrics;
+use chrono::{DateTime, Utc};
 use reqwest::{
     header::{self, HeaderMap, HeaderValue},
-    Certificate, Client, Response,
+    Certificate, Client, Method, RequestBuilder,
 };
 use serde::Serialize;
-use std::{env, fmt::Debug, fs, sync::Arc};
-use tracing::instrument;
+use std::{
+    env,
+    fmt::Debug,
+    fs,
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time;
+use totp_rs::TOTP;
+use tracing::{debug, info, instrument, warn};
 use url::Url;
 
+mod auth;
+mod compat;
 mod errors;
 mod http;
+mod retry;
 mod types;
+mod version;
 
-pub use errors::{ConnectError, FetchError, LoginError};
+use auth::{Credentials, Session};
+use compat::{AuthTransport, Compatibility, Endpoint};
+pub use errors::{ConnectError, FetchError, LoginError, ResolveError};
 use http::{
-    CommunityResponse, FollowCommunity, GetCommunity, GetPosts, GetPostsResponse, ListCommunities,
-    ListCommunitiesResponse, Login, LoginResponse, NodeInfoResponse, ResolveObject,
+    CommunityResponse, FollowCommunity, GetComments, GetCommentsResponse, GetCommunity,
+    GetFederatedInstancesResponse, GetPosts, GetPostsResponse, GetSiteResponse, ListCommunities,
+    ListCommunitiesResponse, Login, LoginResponse, NodeInfoResponse, Reply, ResolveObject,
     ResolveObjectResponse, WithAuth,
 };
 pub use types::{
-    Community, CommunityView, CommunityViewable, ListingType, PostView, ServerError, SortType,
-    SubscribedType,
+    CommentSortType, CommentView, Community, CommunityView, CommunityViewable, FederatedInstances,
+    ListingType, PostView, ServerError, Site, SortType, SubscribedType,
 };
 
+pub use retry::RetryPolicy;
+pub use version::Version;
+
 const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
+/// The most items Lemmy will return in a single page
+const PAGE_LIMIT: i32 = 50;
+
 /// A wrapper around the Lemmy API
 #[derive(Clone)]
 pub struct LemmyApi {
@@ -33,14 +58,16 @@ pub struct LemmyApi {
 
 struct ApiConfig {
     base: Url,
-    token: Option<String>,
+    compat: Compatibility,
+    session: Session,
+    retry: RetryPolicy,
 }
 
 impl LemmyApi {
     /// Connect to a Lemmy instance
     #[instrument(name = "LemmyApi::connect", fields(%base))]
-    pub async fn connect(base: &Url) -> Result<LemmyApi, ConnectError> {
-        let client = new_client()?;
+    pub async fn connect(base: &Url, timeouts: Timeouts) -> Result<LemmyApi, ConnectError> {
+        let client = new_client(timeouts)?;
 
         let info = node_info(&client, base).await?;
         if info.version != "2.0" || info.software.name != "lemmy" {
@@ -56,14 +83,53 @@ impl LemmyApi {
             return Err(ConnectError::FederationNotSupported);
         }
 
-        let base = base.join("/api/v3/").expect("url must be valid");
+        let version = Version::parse(&info.software.version)
+            .ok_or_else(|| ConnectError::UnknownVersion(info.software.version.clone()))?;
+        debug!(%version, "detected lemmy version");
+
+        let compat = Compatibility::new(version)?;
+        let base = base.join(compat.api_path()).expect("url must be valid");
 
         Ok(LemmyApi {
             client,
-            config: Arc::new(ApiConfig { base, token: None }),
+            config: Arc::new(ApiConfig {
+                base,
+                compat,
+                session: Session::default(),
+                retry: RetryPolicy::default(),
+            }),
         })
     }
 
+    /// Get the version of Lemmy the instance is running
+    pub fn version(&self) -> Version {
+        self.config.compat.version()
+    }
+
+    /// Check whether the instance understands the sort method
+    pub fn supports_sort(&self, sort: SortType) -> bool {
+        self.config.compat.supports_sort(sort)
+    }
+
+    /// Attach the authentication token to the request using the instance's supported transport
+    fn authenticate<'t, T>(
+        &self,
+        request: RequestBuilder,
+        payload: T,
+        token: Option<&'t str>,
+    ) -> (RequestBuilder, WithAuth<'t, T>) {
+        match (self.config.compat.auth_transport(), token) {
+            (AuthTransport::Header, Some(token)) => (
+                request.bearer_auth(token),
+                WithAuth {
+                    payload,
+                    auth: None,
+                },
+            ),
+            (_, auth) => (request, WithAuth { payload, auth }),
+        }
+    }
+
     /// Get the instance name
     pub fn instance(&self) -> &str {
         self.config
@@ -72,36 +138,125 @@ impl LemmyApi {
             .expect("api client must have a host")
     }
 
+    /// Retry failed requests according to the policy
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> LemmyApi {
+        let config =
+            Arc::get_mut(&mut self.config).expect("retry policy must be set before cloning");
+        config.retry = policy;
+        self
+    }
+
+    /// Persist the authentication token to the path between restarts
+    pub fn with_token_cache(mut self, path: PathBuf) -> LemmyApi {
+        let config =
+            Arc::get_mut(&mut self.config).expect("token cache must be set before cloning");
+        config.session = Session::with_cache(path);
+        self
+    }
+
     /// Authenticate with the instance
+    ///
+    /// The credentials are kept so the client can transparently re-authenticate if the token
+    /// expires or is revoked.
     #[instrument(
         name = "LemmyApi::login",
-        skip(self, password),
+        skip(self, password, totp),
         fields(base_url = %self.config.base),
     )]
-    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), LoginError> {
+    pub async fn login(
+        &self,
+        username: &str,
+        password: &str,
+        totp: Option<TOTP>,
+    ) -> Result<(), LoginError> {
+        let session = &self.config.session;
+        let credentials = Credentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            totp,
+        };
+
+        let token = match session.load_cached(self.instance(), username) {
+            Some(token) => token,
+            None => {
+                let token = self.request_token(&credentials).await?;
+                session.store_cached(self.instance(), username, &token);
+                token
+            }
+        };
+
+        session.set_credentials(credentials);
+        session.set_token(token);
+
+        Ok(())
+    }
+
+    /// Exchange the credentials for a new token
+    async fn request_token(&self, credentials: &Credentials) -> Result<String, LoginError> {
+        let payload = Login {
+            username: &credentials.username,
+            password: &credentials.password,
+            totp_2fa_token: credentials
+                .totp
+                .as_ref()
+                .map(|totp| totp.generate(Utc::now().timestamp() as u64)),
+        };
         let response = self
-            .post("user/login", Login { username, password })
+            .send_once(Method::POST, Endpoint::Login, &payload, None)
             .await?;
 
         if response.status().is_success() {
-            let auth = response.json::<LoginResponse>().await?;
-            let token = auth.jwt.ok_or(LoginError::IncorrectCredentials)?;
-
-            let config = Arc::get_mut(&mut self.config).expect("login must occur before cloning");
-            config.token = Some(token);
-
-            Ok(())
+            let auth = response.json::<LoginResponse>()?;
+            auth.jwt.ok_or(LoginError::IncorrectCredentials)
         } else {
-            let error = response.json::<ServerError>().await?;
+            let error = response.json::<ServerError>()?;
 
             match error.error.as_str() {
                 "incorrect_login" => Err(LoginError::IncorrectCredentials),
                 "email_not_verified" => Err(LoginError::EmailNotVerified),
+                "missing_totp_token" => Err(LoginError::MissingTotpToken),
+                "incorrect_totp_token" => Err(LoginError::IncorrectTotpToken),
                 _ => Err(LoginError::ServerError(error)),
             }
         }
     }
 
+    /// Re-authenticate after the token was rejected, returning whether a new token is available
+    #[instrument(
+        name = "LemmyApi::refresh_token",
+        skip_all,
+        fields(base_url = %self.config.base),
+    )]
+    async fn refresh_token(&self, rejected: &str) -> bool {
+        let session = &self.config.session;
+        let _guard = session.refresh.lock().await;
+
+        // Another request may have already refreshed the token while we were waiting
+        if session.token().as_deref() != Some(rejected) {
+            return true;
+        }
+
+        let Some(credentials) = session.credentials() else {
+            return false;
+        };
+
+        match self.request_token(&credentials).await {
+            Ok(token) => {
+                info!("token was rejected, successfully logged in again");
+                session.store_cached(self.instance(), &credentials.username, &token);
+                session.set_token(token);
+                true
+            }
+            Err(error) => {
+                warn!(
+                    error = &error as &(dyn std::error::Error + 'static),
+                    "token was rejected and logging in again failed"
+                );
+                false
+            }
+        }
+    }
+
     /// Follow / subscribe to a community
     #[instrument(
         name = "LemmyApi::follow_community",
@@ -109,17 +264,32 @@ impl LemmyApi {
         fields(base_url = %self.config.base),
     )]
     pub async fn follow_community(&self, id: i32) -> Result<(), FetchError> {
+        self.set_follow(id, true).await
+    }
+
+    /// Unfollow / unsubscribe from a community
+    #[instrument(
+        name = "LemmyApi::unfollow_community",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn unfollow_community(&self, id: i32) -> Result<(), FetchError> {
+        self.set_follow(id, false).await
+    }
+
+    /// Change the subscription state of a community
+    async fn set_follow(&self, id: i32, follow: bool) -> Result<(), FetchError> {
         let payload = FollowCommunity {
             community_id: id,
-            follow: true,
+            follow,
         };
 
-        let response = self.post("community/follow", payload).await?;
+        let response = self.post(Endpoint::FollowCommunity, payload).await?;
 
         if response.status().is_success() {
             Ok(())
         } else {
-            let error = response.json().await?;
+            let error = response.json()?;
             Err(FetchError::ServerError(error))
         }
     }
@@ -131,13 +301,13 @@ impl LemmyApi {
         fields(base_url = %self.config.base),
     )]
     pub async fn get_community(&self, name: &str) -> Result<Option<CommunityResponse>, FetchError> {
-        let response = self.get("community", GetCommunity { name }).await?;
+        let response = self.get(Endpoint::Community, GetCommunity { name }).await?;
 
         if response.status().is_success() {
-            let community = response.json().await?;
+            let community = response.json()?;
             Ok(Some(community))
         } else {
-            let error = response.json::<ServerError>().await?;
+            let error = response.json::<ServerError>()?;
             match error.error.as_str() {
                 "couldnt_find_community" => Ok(None),
                 _ => Err(FetchError::ServerError(error)),
@@ -146,6 +316,8 @@ impl LemmyApi {
     }
 
     /// Get / fetch posts, with various filters
+    ///
+    /// Limits above what Lemmy returns at once are fetched across multiple pages.
     #[instrument(
         name = "LemmyApi::get_posts",
         skip(self),
@@ -158,25 +330,107 @@ impl LemmyApi {
         community_id: Option<i32>,
         limit: i32,
     ) -> Result<Vec<PostView>, FetchError> {
+        if !self.supports_sort(sort) {
+            return Err(FetchError::UnsupportedSort);
+        }
+
+        paginate(limit, |page, limit| {
+            self.posts_page(type_, sort, community_id, page, limit)
+        })
+        .await
+    }
+
+    /// Get / fetch the posts published since the time, newest first
+    ///
+    /// Pages are fetched until one reaches back past the time, up to the limit.
+    #[instrument(
+        name = "LemmyApi::get_posts_since",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn get_posts_since(
+        &self,
+        type_: ListingType,
+        since: DateTime<Utc>,
+        limit: i32,
+    ) -> Result<Vec<PostView>, FetchError> {
+        let mut posts = paginate_while(
+            limit,
+            |page, limit| self.posts_page(type_, SortType::New, None, page, limit),
+            |page: &[PostView]| page.last().is_some_and(|p| p.post.published >= since),
+        )
+        .await?;
+
+        // Pinned posts come first regardless of when they were published
+        posts.retain(|p| p.post.published >= since);
+        Ok(posts)
+    }
+
+    /// Fetch a single page of posts
+    async fn posts_page(
+        &self,
+        type_: ListingType,
+        sort: SortType,
+        community_id: Option<i32>,
+        page: Page,
+        limit: i32,
+    ) -> Result<(Vec<PostView>, Option<String>), FetchError> {
         let payload = GetPosts {
             type_,
             sort,
             community_id,
-            page: 1,
+            page: page.number(),
+            page_cursor: page.cursor(),
             limit,
         };
-        let response = self.get("post/list", payload).await?;
+        let response = self.get(Endpoint::ListPosts, payload).await?;
 
         if response.status().is_success() {
-            let posts_response = response.json::<GetPostsResponse>().await?;
-            Ok(posts_response.posts)
+            let posts_response = response.json::<GetPostsResponse>()?;
+            Ok((posts_response.posts, posts_response.next_page))
         } else {
-            let error = response.json().await?;
+            let error = response.json()?;
             Err(FetchError::ServerError(error))
         }
     }
 
+    /// Get / fetch comments, with various filters
+    ///
+    /// Limits above what Lemmy returns at once are fetched across multiple pages.
+    #[instrument(
+        name = "LemmyApi::get_comments",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn get_comments(
+        &self,
+        type_: ListingType,
+        sort: CommentSortType,
+        limit: i32,
+    ) -> Result<Vec<CommentView>, FetchError> {
+        paginate(limit, |page, limit| async move {
+            let payload = GetComments {
+                type_,
+                sort,
+                page: page.number(),
+                limit,
+            };
+            let response = self.get(Endpoint::ListComments, payload).await?;
+
+            if response.status().is_success() {
+                let comments_response = response.json::<GetCommentsResponse>()?;
+                Ok((comments_response.comments, None))
+            } else {
+                let error = response.json()?;
+                Err(FetchError::ServerError(error))
+            }
+        })
+        .await
+    }
+
     /// List communities, with various filters
+    ///
+    /// Limits above what Lemmy returns at once are fetched across multiple pages.
     #[instrument(
         name = "LemmyApi::list_communities",
         skip(self),
@@ -189,20 +443,74 @@ impl LemmyApi {
         show_nsfw: bool,
         limit: i32,
     ) -> Result<Vec<CommunityView>, FetchError> {
-        let payload = ListCommunities {
-            type_,
-            sort,
-            show_nsfw,
-            page: 1,
-            limit,
-        };
-        let response = self.get("community/list", payload).await?;
+        if !self.supports_sort(sort) {
+            return Err(FetchError::UnsupportedSort);
+        }
+
+        paginate(limit, |page, limit| async move {
+            let payload = ListCommunities {
+                type_,
+                sort,
+                show_nsfw,
+                page: page.number(),
+                limit,
+            };
+            let response = self.get(Endpoint::ListCommunities, payload).await?;
+
+            if response.status().is_success() {
+                let communities_response = response.json::<ListCommunitiesResponse>()?;
+                Ok((communities_response.communities, None))
+            } else {
+                let error = response.json()?;
+                Err(FetchError::ServerError(error))
+            }
+        })
+        .await
+    }
+
+    /// List every community the account follows, including pending follows
+    #[instrument(
+        name = "LemmyApi::list_subscribed",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn list_subscribed(&self) -> Result<Vec<CommunityView>, FetchError> {
+        self.list_communities(ListingType::Subscribed, SortType::New, true, i32::MAX)
+            .await
+    }
+
+    /// Get the instances the site federates with
+    #[instrument(
+        name = "LemmyApi::federated_instances",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn federated_instances(&self) -> Result<FederatedInstances, FetchError> {
+        let response = self.get(Endpoint::FederatedInstances, ()).await?;
+
+        if response.status().is_success() {
+            let federated = response.json::<GetFederatedInstancesResponse>()?;
+            Ok(federated.federated_instances.unwrap_or_default())
+        } else {
+            let error = response.json()?;
+            Err(FetchError::ServerError(error))
+        }
+    }
+
+    /// Get the site's details
+    #[instrument(
+        name = "LemmyApi::site",
+        skip(self),
+        fields(base_url = %self.config.base),
+    )]
+    pub async fn site(&self) -> Result<Site, FetchError> {
+        let response = self.get(Endpoint::Site, ()).await?;
 
         if response.status().is_success() {
-            let communities_response = response.json::<ListCommunitiesResponse>().await?;
-            Ok(communities_response.communities)
+            let site = response.json::<GetSiteResponse>()?;
+            Ok(site.site_view.site)
         } else {
-            let error = response.json().await?;
+            let error = response.json()?;
             Err(FetchError::ServerError(error))
         }
     }
@@ -214,13 +522,15 @@ impl LemmyApi {
         fields(base_url = %self.config.base),
     )]
     pub async fn resolve_object(&self, q: &str) -> Result<Option<CommunityView>, FetchError> {
-        let response = self.get("resolve_object", ResolveObject { q }).await?;
+        let response = self
+            .get(Endpoint::ResolveObject, ResolveObject { q })
+            .await?;
 
         if response.status().is_success() {
-            let resolved = response.json::<ResolveObjectResponse>().await?;
+            let resolved = response.json::<ResolveObjectResponse>()?;
             Ok(resolved.community)
         } else {
-            let error = response.json::<ServerError>().await?;
+            let error = response.json::<ServerError>()?;
             match error.error.as_str() {
                 "couldnt_find_object" => Ok(None),
                 _ => Err(FetchError::ServerError(error)),
@@ -228,8 +538,36 @@ impl LemmyApi {
         }
     }
 
-    /// Construct a URL from the base
-    fn url(&self, path: &str) -> Url {
+    /// Resolve a community by its actor id, fetching it through federation if it isn't known yet
+    ///
+    /// The returned view uses this instance's ids, so it can be followed from here. Instances
+    /// sometimes resolve an actor id to a different community, which is reported as a mismatch.
+    #[instrument(
+        name = "LemmyApi::resolve_community",
+        skip(self, actor_id),
+        fields(%actor_id, base_url = %self.config.base),
+    )]
+    pub async fn resolve_community(
+        &self,
+        actor_id: &Url,
+    ) -> Result<Option<CommunityView>, ResolveError> {
+        let Some(view) = self.resolve_object(actor_id.as_str()).await? else {
+            return Ok(None);
+        };
+
+        if !same_actor(actor_id, &view.community.actor_id) {
+            return Err(ResolveError::Mismatch {
+                requested: actor_id.clone(),
+                resolved: view.community.actor_id,
+            });
+        }
+
+        Ok(Some(view))
+    }
+
+    /// Construct the URL for an endpoint
+    fn url(&self, endpoint: Endpoint) -> Url {
+        let path = self.config.compat.endpoint(endpoint);
         self.config.base.join(path).expect("url must be valid")
     }
 
@@ -240,18 +578,11 @@ impl LemmyApi {
         skip(self),
         fields(base_url = %self.config.base),
     )]
-    async fn get<T>(&self, path: &str, query: T) -> Result<Response, reqwest::Error>
+    async fn get<T>(&self, endpoint: Endpoint, query: T) -> Result<Reply, reqwest::Error>
     where
         T: Serialize + Debug,
     {
-        self.client
-            .get(self.url(path))
-            .query(&WithAuth {
-                payload: query,
-                auth: self.config.token.as_deref(),
-            })
-            .send()
-            .await
+        self.send(Method::GET, endpoint, query).await
     }
 
     /// Send a POST request
@@ -261,29 +592,260 @@ impl LemmyApi {
         skip(self),
         fields(base_url = %self.config.base),
     )]
-    async fn post<T>(&self, path: &str, payload: T) -> Result<Response, reqwest::Error>
+    async fn post<T>(&self, endpoint: Endpoint, payload: T) -> Result<Reply, reqwest::Error>
     where
         T: Serialize + Debug,
     {
-        self.client
-            .post(self.url(path))
-            .json(&WithAuth {
-                payload,
-                auth: self.config.token.as_deref(),
-            })
-            .send()
-            .await
+        self.send(Method::POST, endpoint, payload).await
+    }
+
+    /// Send an authenticated request, logging in again and retrying if the token was rejected
+    async fn send<T>(
+        &self,
+        method: Method,
+        endpoint: Endpoint,
+        payload: T,
+    ) -> Result<Reply, reqwest::Error>
+    where
+        T: Serialize,
+    {
+        let token = self.config.session.token();
+        let reply = self
+            .send_once(method.clone(), endpoint, &payload, token.as_deref())
+            .await?;
+
+        if let Some(rejected) = token {
+            if reply.is_auth_error() && self.refresh_token(&rejected).await {
+                let token = self.config.session.token();
+                return self
+                    .send_once(method, endpoint, &payload, token.as_deref())
+                    .await;
+            }
+        }
+
+        Ok(reply)
+    }
+
+    /// Send a request with the given token, retrying if it fails transiently
+    async fn send_once<T>(
+        &self,
+        method: Method,
+        endpoint: Endpoint,
+        payload: T,
+        token: Option<&str>,
+    ) -> Result<Reply, reqwest::Error>
+    where
+        T: Serialize,
+    {
+        let policy = self.config.retry;
+
+        let mut retries = 0;
+        loop {
+            let result = self
+                .attempt(method.clone(), endpoint, &payload, token)
+                .await;
+
+            let retry_after = match &result {
+                Ok(reply) if reply.is_transient() => reply.retry_after(),
+                Err(error) if is_transient(error) => None,
+                _ => {
+                    if retries > 0 {
+                        debug!(?endpoint, retries, "request completed after retrying");
+                    }
+                    return result;
+                }
+            };
+
+            if retries >= policy.max_retries {
+                warn!(?endpoint, retries, "request failed, giving up");
+                return result;
+            }
+            retries += 1;
+
+            let delay = policy.delay(retries, retry_after);
+            match &result {
+                Ok(reply) => warn!(
+                    ?endpoint,
+                    status = %reply.status(),
+                    retry = retries,
+                    max_retries = policy.max_retries,
+                    ?delay,
+                    "request failed, retrying"
+                ),
+                Err(error) => warn!(
+                    ?endpoint,
+                    error = error as &(dyn std::error::Error + 'static),
+                    retry = retries,
+                    max_retries = policy.max_retries,
+                    ?delay,
+                    "request failed, retrying"
+                ),
+            }
+
+            time::sleep(delay).await;
+        }
+    }
+
+    /// Make a single attempt at sending the request
+    async fn attempt<T>(
+        &self,
+        method: Method,
+        endpoint: Endpoint,
+        payload: T,
+        token: Option<&str>,
+    ) -> Result<Reply, reqwest::Error>
+    where
+        T: Serialize,
+    {
+        let request = self.client.request(method.clone(), self.url(endpoint));
+        let (request, payload) = self.authenticate(request, payload, token);
+
+        let request = if method == Method::GET {
+            request.query(&payload)
+        } else {
+            request.json(&payload)
+        };
+
+        let started_at = Instant::now();
+        let result = match request.send().await {
+            Ok(response) => Reply::read(response).await,
+            Err(error) => Err(error),
+        };
+
+        let endpoint = format!("{endpoint:?}");
+        let status = result.as_ref().ok().map(Reply::status);
+        metrics::request(
+            self.instance(),
+            &endpoint,
+            status.map(|s| s.as_u16()),
+            started_at.elapsed(),
+        );
+        if let Ok(reply) = &result {
+            if !reply.status().is_success() {
+                let error = reply.json::<ServerError>();
+                let code = error.as_ref().map_or("unknown", |e| e.error.as_str());
+                metrics::server_error(self.instance(), &endpoint, code);
+            }
+        }
+
+        result
+    }
+}
+
+/// Where a page of results starts
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Page {
+    /// The page number, starting from 1
+    Number(i32),
+    /// The cursor returned with the previous page
+    Cursor(String),
+}
+
+impl Page {
+    /// The page number, if the page isn't requested by cursor
+    fn number(&self) -> Option<i32> {
+        match self {
+            Self::Number(number) => Some(*number),
+            Self::Cursor(_) => None,
+        }
+    }
+
+    /// The cursor, if the page isn't requested by number
+    fn cursor(&self) -> Option<&str> {
+        match self {
+            Self::Number(_) => None,
+            Self::Cursor(cursor) => Some(cursor),
+        }
+    }
+}
+
+/// Fetch pages of results until the limit is reached or the results run out
+///
+/// The fetch is given the page and its size, and returns the results along with the cursor for
+/// the next page if the instance supports cursors. Otherwise, pages are requested by number.
+async fn paginate<T, F, Fut>(limit: i32, fetch: F) -> Result<Vec<T>, FetchError>
+where
+    F: FnMut(Page, i32) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), FetchError>>,
+{
+    paginate_while(limit, fetch, |_| true).await
+}
+
+/// Like [`paginate`], but also stops once `more` returns false for a page
+async fn paginate_while<T, F, Fut>(
+    limit: i32,
+    mut fetch: F,
+    mut more: impl FnMut(&[T]) -> bool,
+) -> Result<Vec<T>, FetchError>
+where
+    F: FnMut(Page, i32) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), FetchError>>,
+{
+    let limit = usize::try_from(limit).unwrap_or_default();
+    let size = limit.min(PAGE_LIMIT as usize);
+
+    let mut results = Vec::new();
+    let mut page = Page::Number(1);
+    while results.len() < limit {
+        let (items, next) = fetch(page.clone(), size as i32).await?;
+        let exhausted = items.len() < size || !more(&items);
+        results.extend(items);
+        if exhausted {
+            break;
+        }
+
+        page = match (next, page) {
+            (Some(cursor), _) => Page::Cursor(cursor),
+            (None, Page::Number(number)) => Page::Number(number + 1),
+            (None, Page::Cursor(_)) => break,
+        };
+    }
+
+    results.truncate(limit);
+    Ok(results)
+}
+
+/// Whether the actor ids refer to the same actor, ignoring case and trailing slashes
+fn same_actor(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str()
+        && a.path()
+            .trim_end_matches('/')
+            .eq_ignore_ascii_case(b.path().trim_end_matches('/'))
+}
+
+/// Whether the error might not occur if the request is retried
+fn is_transient(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request() || error.is_body()
+}
+
+/// How long to wait on an instance before giving up on a request
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// How long the whole request may take, from connecting until the response is read
+    pub request: Duration,
+    /// How long establishing the connection may take
+    pub connect: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            request: Duration::from_secs(30),
+            connect: Duration::from_secs(10),
+        }
     }
 }
 
 /// Construct a new HTTP client
-fn new_client() -> Result<Client, ConnectError> {
+fn new_client(timeouts: Timeouts) -> Result<Client, ConnectError> {
     let mut headers = HeaderMap::new();
     headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
 
     let mut builder = Client::builder()
         .user_agent(USER_AGENT)
-        .default_headers(headers);
+        .default_headers(headers)
+        .timeout(timeouts.request)
+        .connect_timeout(timeouts.connect);
 
     if let Ok(paths) = env::var("EXTRA_CERTIFICATE_PATHS") {
         for path in paths.split(',') {
@@ -308,3 +870,114 @@ async fn node_info(client: &Client, url: &Url) -> Result<NodeInfoResponse, Conne
 
     Ok(info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{paginate, paginate_while, FetchError, Page};
+    use std::{
+        cell::RefCell,
+        future::{ready, Ready},
+    };
+
+    type Response = Result<(Vec<usize>, Option<String>), FetchError>;
+
+    /// A fake instance serving `total` numbered items, recording the pages requested from it
+    ///
+    /// When `cursors` is set, every page but the last returns a cursor to the next one.
+    fn fake(
+        total: usize,
+        cursors: bool,
+        requests: &RefCell<Vec<(Page, i32)>>,
+    ) -> impl FnMut(Page, i32) -> Ready<Response> + '_ {
+        move |page, size| {
+            requests.borrow_mut().push((page.clone(), size));
+
+            let start = match &page {
+                Page::Number(number) => (*number as usize - 1) * size as usize,
+                Page::Cursor(cursor) => cursor.parse().expect("cursor must be a number"),
+            };
+            let end = (start + size as usize).min(total);
+            let items = (start.min(end)..end).collect::<Vec<_>>();
+            let next = (cursors && end < total).then(|| end.to_string());
+
+            ready(Ok((items, next)))
+        }
+    }
+
+    #[tokio::test]
+    async fn limit_not_a_multiple_of_the_page_size() {
+        let requests = RefCell::new(Vec::new());
+        let results = paginate(120, fake(500, false, &requests)).await.unwrap();
+
+        assert_eq!(results, (0..120).collect::<Vec<_>>());
+        assert_eq!(
+            requests.into_inner(),
+            [
+                (Page::Number(1), 50),
+                (Page::Number(2), 50),
+                (Page::Number(3), 50),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn limit_smaller_than_the_page_size() {
+        let requests = RefCell::new(Vec::new());
+        let results = paginate(10, fake(500, false, &requests)).await.unwrap();
+
+        assert_eq!(results, (0..10).collect::<Vec<_>>());
+        assert_eq!(requests.into_inner(), [(Page::Number(1), 10)]);
+    }
+
+    #[tokio::test]
+    async fn stops_after_a_short_page() {
+        let requests = RefCell::new(Vec::new());
+        let results = paginate(200, fake(70, false, &requests)).await.unwrap();
+
+        assert_eq!(results, (0..70).collect::<Vec<_>>());
+        assert_eq!(
+            requests.into_inner(),
+            [(Page::Number(1), 50), (Page::Number(2), 50)]
+        );
+    }
+
+    #[tokio::test]
+    async fn switches_to_cursors() {
+        let requests = RefCell::new(Vec::new());
+        let results = paginate(200, fake(130, true, &requests)).await.unwrap();
+
+        assert_eq!(results, (0..130).collect::<Vec<_>>());
+        assert_eq!(
+            requests.into_inner(),
+            [
+                (Page::Number(1), 50),
+                (Page::Cursor("50".to_owned()), 50),
+                (Page::Cursor("100".to_owned()), 50),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn non_positive_limit_fetches_nothing() {
+        for limit in [0, -1, i32::MIN] {
+            let requests = RefCell::new(Vec::new());
+            let results = paginate(limit, fake(500, false, &requests)).await.unwrap();
+
+            assert!(results.is_empty());
+            assert!(requests.into_inner().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn stops_once_no_more_pages_are_wanted() {
+        let requests = RefCell::new(Vec::new());
+        let results = paginate_while(500, fake(500, false, &requests), |page| {
+            page.last().is_some_and(|&item| item < 80)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(results, (0..100).collect::<Vec<_>>());
+        assert_eq!(requests.into_inner().len(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+use totp_rs::TOTP;
+use tracing::{debug, warn};
+
+/// The authentication state, shared between every clone of a client
+#[derive(Default)]
+pub struct Session {
+    token: RwLock<Option<String>>,
+    credentials: Mutex<Option<Credentials>>,
+    /// Held while re-authenticating so concurrent requests only log in once
+    pub refresh: tokio::sync::Mutex<()>,
+    cache: Option<PathBuf>,
+}
+
+/// The credentials used to log in
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    pub totp: Option<TOTP>,
+}
+
+/// A token persisted between restarts
+#[derive(Deserialize, Serialize)]
+struct CachedToken {
+    instance: String,
+    username: String,
+    jwt: String,
+}
+
+impl Session {
+    /// Create a session that persists its token to the given path
+    pub fn with_cache(path: PathBuf) -> Session {
+        Session {
+            cache: Some(path),
+            ..Default::default()
+        }
+    }
+
+    /// Get the current token
+    pub fn token(&self) -> Option<String> {
+        self.token.read().expect("token lock poisoned").clone()
+    }
+
+    /// Replace the current token
+    pub fn set_token(&self, token: String) {
+        *self.token.write().expect("token lock poisoned") = Some(token);
+    }
+
+    /// Get the credentials to re-authenticate with
+    pub fn credentials(&self) -> Option<Credentials> {
+        self.credentials
+            .lock()
+            .expect("credentials lock poisoned")
+            .clone()
+    }
+
+    /// Store the credentials to re-authenticate with
+    pub fn set_credentials(&self, credentials: Credentials) {
+        *self.credentials.lock().expect("credentials lock poisoned") = Some(credentials);
+    }
+
+    /// Load the cached token if it belongs to the user on the instance
+    pub fn load_cached(&self, instance: &str, username: &str) -> Option<String> {
+        let path = self.cache.as_ref()?;
+
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(error) => {
+                warn!(path = %path.display(), error = &error as &(dyn std::error::Error + 'static), "failed to read token cache");
+                return None;
+            }
+        };
+
+        match serde_json::from_slice::<CachedToken>(&contents) {
+            Ok(cached) if cached.instance == instance && cached.username == username => {
+                debug!(path = %path.display(), "using cached token");
+                Some(cached.jwt)
+            }
+            Ok(_) => None,
+            Err(error) => {
+                warn!(path = %path.display(), error = &error as &(dyn std::error::Error + 'static), "invalid token cache");
+                None
+            }
+        }
+    }
+
+    /// Persist the token to the cache, if there is one
+    pub fn store_cached(&self, instance: &str, username: &str, jwt: &str) {
+        let Some(path) = &self.cache else {
+            return;
+        };
+
+        let cached = CachedToken {
+            instance: instance.to_owned(),
+            username: username.to_owned(),
+            jwt: jwt.to_owned(),
+        };
+        if let Err(error) = write_private(path, &cached) {
+            warn!(path = %path.display(), error = &error as &(dyn std::error::Error + 'static), "failed to write token cache");
+        }
+    }
+}
+
+/// Write the token to a file only readable by the current user
+fn write_private(path: &Path, cached: &CachedToken) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    serde_json::to_writer(&mut file, cached)?;
+    file.flush()
+}
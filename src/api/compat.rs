@@ -0,0 +1,93 @@
+use super::{errors::ConnectError, types::SortType, Version};
+
+/// The oldest supported version of Lemmy
+const MINIMUM_VERSION: Version = Version::new(0, 18, 0);
+/// The first version of Lemmy that is no longer supported
+const UNSUPPORTED_VERSION: Version = Version::new(0, 20, 0);
+
+/// Describes the differences between the supported versions of the Lemmy API
+#[derive(Clone, Copy, Debug)]
+pub struct Compatibility {
+    version: Version,
+}
+
+/// How the authentication token is sent to the instance
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthTransport {
+    /// As an `auth` query parameter or body field, used before v0.19
+    Parameter,
+    /// As an `Authorization: Bearer` header, used since v0.19
+    Header,
+}
+
+/// The API endpoints used by moco
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Endpoint {
+    Community,
+    FederatedInstances,
+    FollowCommunity,
+    ListComments,
+    ListCommunities,
+    ListPosts,
+    Login,
+    ResolveObject,
+    Site,
+}
+
+impl Compatibility {
+    /// Determine how to talk to the version, if it is supported
+    pub fn new(version: Version) -> Result<Compatibility, ConnectError> {
+        if version < MINIMUM_VERSION || version >= UNSUPPORTED_VERSION {
+            return Err(ConnectError::UnsupportedVersion(version));
+        }
+
+        Ok(Compatibility { version })
+    }
+
+    /// The version of Lemmy
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// The path to the root of the API
+    pub fn api_path(&self) -> &'static str {
+        "/api/v3/"
+    }
+
+    /// The path of the endpoint, relative to the root of the API
+    pub fn endpoint(&self, endpoint: Endpoint) -> &'static str {
+        match endpoint {
+            Endpoint::Community => "community",
+            Endpoint::FederatedInstances => "federated_instances",
+            Endpoint::FollowCommunity => "community/follow",
+            Endpoint::ListComments => "comment/list",
+            Endpoint::ListCommunities => "community/list",
+            Endpoint::ListPosts => "post/list",
+            Endpoint::Login => "user/login",
+            Endpoint::ResolveObject => "resolve_object",
+            Endpoint::Site => "site",
+        }
+    }
+
+    /// How the authentication token should be sent
+    pub fn auth_transport(&self) -> AuthTransport {
+        if self.version >= Version::new(0, 19, 0) {
+            AuthTransport::Header
+        } else {
+            AuthTransport::Parameter
+        }
+    }
+
+    /// Whether the sort method is understood by the version
+    pub fn supports_sort(&self, sort: SortType) -> bool {
+        let introduced = match sort {
+            SortType::TopThreeMonths | SortType::TopSixMonths | SortType::TopNineMonths => {
+                Version::new(0, 18, 1)
+            }
+            SortType::Controversial | SortType::Scaled => Version::new(0, 19, 0),
+            _ => MINIMUM_VERSION,
+        };
+
+        self.version >= introduced
+    }
+}
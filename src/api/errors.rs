@@ -1,8 +1,9 @@
-use super::types::ServerError;
+use super::{types::ServerError, Version};
 use std::{
     fmt::{self, Formatter},
     io,
 };
+use url::Url;
 
 macro_rules! error {
     (
@@ -24,6 +25,8 @@ macro_rules! error {
             ServerError(ServerError),
             /// An error that occurred while processing the request
             Request(reqwest::Error),
+            /// The server's response could not be understood
+            Deserialize(serde_json::Error),
         }
 
         impl fmt::Display for $name {
@@ -39,6 +42,7 @@ macro_rules! error {
                         Ok(())
                     }
                     Self::Request(_) => write!(f, "failed to complete the request"),
+                    Self::Deserialize(_) => write!(f, "invalid response from the server"),
                 }
             }
         }
@@ -47,6 +51,7 @@ macro_rules! error {
             fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
                 match self {
                     Self::Request(err) => Some(err),
+                    Self::Deserialize(err) => Some(err),
                     _ => None,
                 }
             }
@@ -57,6 +62,12 @@ macro_rules! error {
                 Self::Request(err)
             }
         }
+
+        impl From<serde_json::Error> for $name {
+            fn from(err: serde_json::Error) -> Self {
+                Self::Deserialize(err)
+            }
+        }
     };
 }
 
@@ -67,6 +78,10 @@ pub enum ConnectError {
     FederationNotSupported,
     /// The server is not a Lemmy instance
     NotLemmyInstance,
+    /// The server's Lemmy version could not be determined
+    UnknownVersion(String),
+    /// The server's Lemmy version is not supported
+    UnsupportedVersion(Version),
     /// An error that occurred while processing the request
     Request(reqwest::Error),
     /// Invalid additional certificate
@@ -99,6 +114,10 @@ impl fmt::Display for ConnectError {
             Self::NotLemmyInstance => {
                 write!(f, "the requested URL does not resolve to a Lemmy instance")
             }
+            Self::UnknownVersion(version) => write!(f, "unknown lemmy version {version:?}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "lemmy version {version} is not supported")
+            }
             Self::Request(_) => write!(f, "failed to complete the request"),
             Self::InvalidCertificate(_) => write!(f, "invalid extra certificate"),
             Self::CertificateRead(_) => write!(f, "could not read extra certificate path"),
@@ -124,6 +143,50 @@ error!(
         IncorrectCredentials => "invalid username or password",
         /// The user's email is not verified
         EmailNotVerified => "user's email not verified",
+        /// The user has two-factor authentication enabled, but no TOTP secret was provided
+        MissingTotpToken => "user has two-factor authentication enabled, but no TOTP secret was provided",
+        /// The generated two-factor authentication token was rejected
+        IncorrectTotpToken => "invalid two-factor authentication token",
+);
+
+error!(
+    FetchError
+        /// The sort method is not supported by the instance's version of Lemmy
+        UnsupportedSort => "sort method not supported by this version of lemmy",
 );
 
-error!(FetchError);
+/// Errors that can occur when resolving a community through an instance
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The instance resolved a different community than the one requested
+    Mismatch { requested: Url, resolved: Url },
+    /// The community could not be fetched
+    Fetch(FetchError),
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Mismatch { .. } => None,
+            Self::Fetch(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch {
+                requested,
+                resolved,
+            } => write!(f, "requested community {requested} but resolved {resolved}"),
+            Self::Fetch(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<FetchError> for ResolveError {
+    fn from(err: FetchError) -> ResolveError {
+        Self::Fetch(err)
+    }
+}
@@ -1,5 +1,94 @@
-use super::types::{CommunityView, ListingType, PostView, SortType};
-use serde::{Deserialize, Serialize};
+use super::types::{
+    CommentSortType, CommentView, CommunityView, FederatedInstances, ListingType, PostView,
+    ServerError, Site, SortType,
+};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::{self, Formatter},
+    time::Duration,
+};
+
+/// A response whose body has been read, allowing it to be inspected multiple times
+pub(crate) struct Reply {
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    /// Read the response's body
+    pub async fn read(response: Response) -> Result<Reply, reqwest::Error> {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.bytes().await?.to_vec();
+
+        Ok(Reply {
+            status,
+            retry_after,
+            body,
+        })
+    }
+
+    /// Get the status code
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// How long the server asked to wait before retrying
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Deserialize the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.body)
+    }
+
+    /// Whether the request was rejected because the token is missing, expired, or revoked
+    pub fn is_auth_error(&self) -> bool {
+        if !matches!(
+            self.status,
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED
+        ) {
+            return false;
+        }
+
+        self.json::<ServerError>()
+            .map(|e| matches!(e.error.as_str(), "not_logged_in" | "incorrect_login"))
+            .unwrap_or_default()
+    }
+
+    /// Whether the request was rate limited or the server is temporarily unavailable
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => true,
+            StatusCode::BAD_REQUEST => self
+                .json::<ServerError>()
+                .map(|e| e.error == "rate_limit_error")
+                .unwrap_or_default(),
+            _ => false,
+        }
+    }
+}
+
+/// Parse a `Retry-After` header, which is either a number of seconds or an HTTP date
+fn parse_retry_after(raw: &str) -> Option<Duration> {
+    if let Ok(seconds) = raw.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = DateTime::parse_from_rfc2822(raw.trim()).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
 
 /// Includes an authentication parameter in the request
 #[derive(Debug, Serialize)]
@@ -14,9 +103,28 @@ pub(crate) struct WithAuth<'a, T> {
 #[derive(Debug, Deserialize)]
 pub struct CommunityResponse {
     pub community_view: CommunityView,
+    #[allow(dead_code)]
     pub discussion_languages: Vec<i32>,
 }
 
+/// The instances the site federates with, unset when federation is disabled
+#[derive(Debug, Deserialize)]
+pub struct GetFederatedInstancesResponse {
+    pub federated_instances: Option<FederatedInstances>,
+}
+
+/// The site's details
+#[derive(Debug, Deserialize)]
+pub struct GetSiteResponse {
+    pub site_view: SiteView,
+}
+
+/// A site view
+#[derive(Debug, Deserialize)]
+pub struct SiteView {
+    pub site: Site,
+}
+
 /// Follow / subscribe to a community
 #[derive(Debug, Serialize)]
 pub struct FollowCommunity {
@@ -31,12 +139,17 @@ pub struct GetCommunity<'n> {
 }
 
 /// Get a list of posts
+///
+/// Either the page number or, since v0.19, the cursor from the previous page must be provided.
 #[derive(Debug, Serialize)]
-pub struct GetPosts {
+pub struct GetPosts<'c> {
     pub type_: ListingType,
     pub sort: SortType,
     pub community_id: Option<i32>,
-    pub page: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_cursor: Option<&'c str>,
     pub limit: i32,
 }
 
@@ -44,6 +157,24 @@ pub struct GetPosts {
 #[derive(Debug, Deserialize)]
 pub struct GetPostsResponse {
     pub posts: Vec<PostView>,
+    /// The cursor for the next page, only returned since v0.19
+    #[serde(default)]
+    pub next_page: Option<String>,
+}
+
+/// Get a list of comments
+#[derive(Debug, Serialize)]
+pub struct GetComments {
+    pub type_: ListingType,
+    pub sort: CommentSortType,
+    pub page: Option<i32>,
+    pub limit: i32,
+}
+
+/// The comment list response
+#[derive(Debug, Deserialize)]
+pub struct GetCommentsResponse {
+    pub comments: Vec<CommentView>,
 }
 
 /// Fetches a list of communities
@@ -52,7 +183,7 @@ pub struct ListCommunities {
     pub type_: ListingType,
     pub sort: SortType,
     pub show_nsfw: bool,
-    pub page: i32,
+    pub page: Option<i32>,
     pub limit: i32,
 }
 
@@ -62,11 +193,26 @@ pub struct ListCommunitiesResponse {
     pub communities: Vec<CommunityView>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
 pub struct Login<'a> {
     #[serde(rename = "username_or_email")]
     pub username: &'a str,
     pub password: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totp_2fa_token: Option<String>,
+}
+
+impl fmt::Debug for Login<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Login")
+            .field("username", &self.username)
+            .field("password", &"**********")
+            .field(
+                "totp_2fa_token",
+                &self.totp_2fa_token.as_ref().map(|_| "******"),
+            )
+            .finish()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -0,0 +1,43 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// The longest to ever wait between attempts, even if the server asks for longer
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// How failed requests are retried
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// How many times to retry a request before giving up
+    pub max_retries: u32,
+    /// How long to wait before the first retry, doubling for each subsequent retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before the retry, starting from 1
+    ///
+    /// The server's requested delay takes precedence over the exponential backoff. Otherwise,
+    /// between half and all of the backoff is waited so that concurrent requests spread out.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(MAX_BACKOFF);
+        }
+
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(MAX_BACKOFF);
+
+        let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+        backoff.mul_f64(jitter)
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use url::Url;
@@ -59,9 +60,28 @@ impl CommunityViewable for CommunityView {
     }
 }
 
+/// A post
+#[derive(Debug, Deserialize)]
+pub struct Post {
+    /// When the post was created
+    #[serde(with = "timestamp")]
+    pub published: DateTime<Utc>,
+    /// Whether the post is pinned to the top of its community
+    #[serde(default)]
+    pub featured_community: bool,
+}
+
+/// The aggregated counts for a post
+#[derive(Debug, Deserialize)]
+pub struct PostAggregates {
+    pub comments: i64,
+}
+
 /// A post view
 #[derive(Debug, Deserialize)]
 pub struct PostView {
+    pub post: Post,
+    pub counts: PostAggregates,
     pub community: Community,
     pub subscribed: SubscribedType,
     pub creator_blocked: bool,
@@ -81,6 +101,30 @@ impl CommunityViewable for PostView {
     }
 }
 
+/// The instances a site federates with
+#[derive(Debug, Default, Deserialize)]
+pub struct FederatedInstances {
+    /// Instances the site exclusively federates with, empty unless it uses an allowlist
+    #[serde(default)]
+    pub allowed: Vec<Instance>,
+    /// Instances the site refuses to federate with
+    #[serde(default)]
+    pub blocked: Vec<Instance>,
+}
+
+/// A site
+#[derive(Debug, Deserialize)]
+pub struct Site {
+    /// The federated actor_id, whose host is the site's domain
+    pub actor_id: Url,
+}
+
+/// A federated instance
+#[derive(Debug, Deserialize)]
+pub struct Instance {
+    pub domain: String,
+}
+
 /// A server error
 #[derive(Debug, Deserialize)]
 pub struct ServerError {
@@ -88,8 +132,30 @@ pub struct ServerError {
     pub message: Option<String>,
 }
 
+/// A comment view
+#[derive(Debug, Deserialize)]
+pub struct CommentView {
+    pub community: Community,
+    pub subscribed: SubscribedType,
+    pub creator_blocked: bool,
+}
+
+impl CommunityViewable for CommentView {
+    fn community(&self) -> &Community {
+        &self.community
+    }
+
+    fn subscribed(&self) -> SubscribedType {
+        self.subscribed
+    }
+
+    fn blocked(&self) -> bool {
+        self.creator_blocked
+    }
+}
+
 /// The post sort types
-#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, ValueEnum)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize, ValueEnum)]
 pub enum SortType {
     /// Calculates a rank based on the score and time of the latest comment, with decay over time
     Active,
@@ -125,6 +191,52 @@ pub enum SortType {
     TopSixMonths,
     /// Highest scoring posts during the last 9 months
     TopNineMonths,
+    /// Posts with the most controversial votes first, since v0.19
+    Controversial,
+    /// Like hot, but weighted by the size of the community, since v0.19
+    Scaled,
+}
+
+/// The comment sort types
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum CommentSortType {
+    /// Ranks comments by score and age
+    Hot,
+    /// Highest scoring comments first
+    Top,
+    /// Shows most recent comments first
+    New,
+    /// Shows oldest comments first
+    Old,
+    /// Comments with the most controversial votes first, since v0.19
+    Controversial,
+}
+
+impl From<SortType> for CommentSortType {
+    /// Comments can't be sorted by time period, so the closest sort method is used
+    fn from(sort: SortType) -> CommentSortType {
+        match sort {
+            SortType::Active
+            | SortType::Hot
+            | SortType::MostComments
+            | SortType::NewComments
+            | SortType::Scaled => Self::Hot,
+            SortType::New => Self::New,
+            SortType::Old => Self::Old,
+            SortType::TopDay
+            | SortType::TopWeek
+            | SortType::TopMonth
+            | SortType::TopYear
+            | SortType::TopAll
+            | SortType::TopHour
+            | SortType::TopSixHour
+            | SortType::TopTwelveHour
+            | SortType::TopThreeMonths
+            | SortType::TopSixMonths
+            | SortType::TopNineMonths => Self::Top,
+            SortType::Controversial => Self::Controversial,
+        }
+    }
 }
 
 /// A type / status for a community subscribe
@@ -134,3 +246,24 @@ pub enum SubscribedType {
     NotSubscribed,
     Pending,
 }
+
+/// Lemmy timestamps are sent without a timezone before v0.19, and in RFC 3339 format after
+mod timestamp {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = String::deserialize(deserializer)?;
+
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(&raw) {
+            return Ok(timestamp.with_timezone(&Utc));
+        }
+
+        NaiveDateTime::parse_from_str(&raw, "%Y-%m-%dT%H:%M:%S%.f")
+            .map(|naive| naive.and_utc())
+            .map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt::{self, Formatter};
+
+/// The version of the Lemmy software running on an instance
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Create a new version
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse a version, ignoring any pre-release or build metadata (i.e. `0.19.0-rc.1`)
+    pub fn parse(raw: &str) -> Option<Version> {
+        let core = raw.trim().split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(str::parse::<u32>);
+
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().transpose().ok()?.unwrap_or_default();
+        let patch = parts.next().transpose().ok()?.unwrap_or_default();
+
+        Some(Version::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{cli, ignored::Ignored, populater::periodically};
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+/// The severities in CSV blocklists that mean an instance is defederated
+const DEFEDERATED_SEVERITIES: &[&str] = &["suspend", "block", "defederate"];
+
+/// Shared context passed through to the blocklist importer
+#[derive(Clone)]
+pub struct Context {
+    ignored: Ignored,
+    blocklists: Vec<PathBuf>,
+    /// The instances last imported from each blocklist, kept when a blocklist can't be read
+    imported: Arc<Mutex<Vec<HashSet<String>>>>,
+}
+
+/// Create a new context for the blocklist importer
+pub fn context(ignored: Ignored, blocklists: Vec<PathBuf>) -> Context {
+    let imported = vec![HashSet::new(); blocklists.len()];
+    Context {
+        ignored,
+        blocklists,
+        imported: Arc::new(Mutex::new(imported)),
+    }
+}
+
+/// Periodically refresh the instances imported from the blocklists
+pub async fn launch(context: Context, interval: Duration, stop: broadcast::Receiver<()>) {
+    info!("blocklist importer started");
+
+    periodically(interval, stop, || run(&context)).await;
+
+    info!("blocklist importer halted");
+}
+
+/// Import the blocklists once, merging them into the ignored instances
+pub async fn run(context: &Context) {
+    async {
+        let mut imported = context.imported.lock().expect("imported lock poisoned").clone();
+        for (blocklist, instances) in context.blocklists.iter().zip(&mut imported) {
+            match fs::read_to_string(blocklist) {
+                Ok(contents) => {
+                    let loaded = parse(&contents);
+                    debug!(blocklist = %blocklist.display(), instances = loaded.len(), "loaded blocklist");
+                    *instances = loaded;
+                }
+                Err(error) => {
+                    error!(blocklist = %blocklist.display(), error = &error as &(dyn std::error::Error + 'static), "failed to load blocklist, keeping previous entries");
+                }
+            }
+        }
+
+        let merged = imported.iter().flatten().cloned().collect::<HashSet<_>>();
+        info!(instances = merged.len(), "complete");
+
+        context.ignored.replace_imported(merged);
+        *context.imported.lock().expect("imported lock poisoned") = imported;
+    }
+    .instrument(info_span!("blocklist"))
+    .await
+}
+
+/// Parse a list of domains or a CSV export, skipping invalid entries
+///
+/// CSV exports must have a header with a `domain` column, optionally prefixed with `#` as in
+/// Mastodon's exports. If there is a `severity` column, only defederated instances are included.
+fn parse(contents: &str) -> HashSet<String> {
+    let mut lines = contents.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    let Some(first) = lines.clone().next() else {
+        return HashSet::new();
+    };
+
+    // Only a header naming a domain column marks a CSV, a plain list may start with any comment
+    let columns = first
+        .split(',')
+        .map(|c| c.trim().trim_start_matches('#').to_lowercase())
+        .collect::<Vec<_>>();
+    let (csv, domain_column, severity_column) = match columns.iter().position(|c| c == "domain") {
+        Some(domain) => {
+            lines.next();
+            let severity = columns.iter().position(|c| c == "severity");
+            (true, domain, severity)
+        }
+        None => (false, 0, None),
+    };
+
+    let mut instances = HashSet::new();
+    for line in lines {
+        if !csv && line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        if let Some(severity) = severity_column.and_then(|i| fields.get(i)) {
+            if !DEFEDERATED_SEVERITIES.contains(&severity.to_lowercase().as_str()) {
+                continue;
+            }
+        }
+
+        let Some(domain) = fields.get(domain_column) else {
+            continue;
+        };
+        match cli::parse_domain(domain) {
+            Ok(domain) => {
+                instances.insert(domain);
+            }
+            Err(reason) => warn!(%domain, %reason, "skipping invalid blocklist entry"),
+        }
+    }
+
+    instances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use std::collections::HashSet;
+
+    fn set(domains: &[&str]) -> HashSet<String> {
+        domains.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn plain_list() {
+        let contents = "# blocked instances\n\nbad.example\n  worse.example  \n# spam.example\n";
+        assert_eq!(parse(contents), set(&["bad.example", "worse.example"]));
+    }
+
+    #[test]
+    fn plain_list_with_comma_in_leading_comment() {
+        let contents = "# spam, harassment, and worse\nbad.example\nworse.example\n";
+        assert_eq!(parse(contents), set(&["bad.example", "worse.example"]));
+    }
+
+    #[test]
+    fn mastodon_csv() {
+        let contents =
+            "#domain,#severity,#reject_media,#reject_reports,#public_comment,#obfuscate\n\
+            bad.example,suspend,false,false,spam,false\n\
+            noisy.example,silence,false,false,,false\n\
+            worse.example,Suspend,true,true,\"harassment, spam\",false\n";
+        assert_eq!(parse(contents), set(&["bad.example", "worse.example"]));
+    }
+
+    #[test]
+    fn csv_without_severity() {
+        let contents = "domain\nbad.example\nworse.example\n";
+        assert_eq!(parse(contents), set(&["bad.example", "worse.example"]));
+    }
+
+    #[test]
+    fn empty() {
+        assert!(parse("").is_empty());
+        assert!(parse("\n  \n").is_empty());
+    }
+}
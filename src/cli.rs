@@ -1,23 +1,58 @@
-use crate::api::SortType;
-use clap::Parser;
+use crate::{
+    api::SortType,
+    filter::Pattern,
+    populater::{NsfwPolicy, Source},
+};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use std::{
+    ffi::OsString,
     fmt::{self, Formatter},
+    net::SocketAddr,
+    path::PathBuf,
     time::Duration,
 };
+use totp_rs::TOTP;
 use tracing::{field::display, Level};
 use url::Url;
 
 mod parsers;
 
+pub use parsers::{parse_community, parse_domain, parse_duration};
+
 /// Parse the command line arguments
 pub fn parse() -> Args {
-    Args::parse()
+    try_parse_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+}
+
+/// Parse the arguments, keeping the raw matches so explicitly set arguments can be detected
+///
+/// Prefer this over [`Parser::try_parse_from`], which discards the matches.
+pub fn try_parse_from<I, T>(itr: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = Args::command().try_get_matches_from(itr)?;
+    let mut args = Args::from_arg_matches(&matches)?;
+    args.matches = matches;
+    Ok(args)
 }
 
 /// Populate your Lemmy instance's All feed with communities and posts
 #[derive(Parser)]
 #[command(author, version, about)]
 pub struct Args {
+    /// Path to a TOML configuration file with default and per-peer settings
+    ///
+    /// Settings are resolved from most to least specific: the peer's table in the configuration
+    /// file, command line arguments and environment variables, the configuration file's
+    /// `[defaults]` table, then the built-in defaults.
+    ///
+    /// The file is reloaded when it changes or when moco receives `SIGHUP`, starting and stopping
+    /// populaters as needed.
+    #[arg(long, env = "CONFIG")]
+    pub config: Option<PathBuf>,
+
     /// The URL of the instance's API
     ///
     /// This can be the URL of the API container or the public URL with the `/api` prefix
@@ -38,6 +73,16 @@ pub struct Args {
         value_parser = parsers::string(),
     )]
     pub password: String,
+    /// The base32-encoded secret for the user's two-factor authentication
+    ///
+    /// Only required if the user has two-factor authentication enabled. This is the secret from
+    /// the `otpauth://` link shown when enabling it, codes are generated from it when logging in.
+    #[arg(
+        long,
+        env = "LOCAL_TOTP_SECRET",
+        value_parser = parsers::totp_secret(),
+    )]
+    pub totp_secret: Option<TOTP>,
 
     /// Comma-separated list of domains for the peer servers to pull from
     #[arg(
@@ -55,6 +100,56 @@ pub struct Args {
         value_parser = parsers::domain(), env = "IGNORED",
     )]
     pub ignored: Vec<String>,
+    /// Comma-separated list of blocklists to ignore instances from, in addition to `--ignored`
+    ///
+    /// Each entry is the path to a file containing one domain per line, or a CSV export of a
+    /// Mastodon or Lemmy blocklist, where only instances with a `suspend`, `block`, or
+    /// `defederate` severity are imported.
+    /// The instances blocked by the local instance are always ignored.
+    #[arg(long, env = "BLOCKLISTS", value_delimiter = ',')]
+    pub blocklists: Vec<PathBuf>,
+    /// How often to re-import the blocklists and the local instance's federation policy
+    ///
+    /// Communities on instances the local instance blocks, or doesn't allow when it uses an
+    /// allowlist, are never followed.
+    ///
+    /// Supports hours, minutes, and seconds unit specifiers with `h`, `m`, and `s` respectively.
+    #[arg(
+        long,
+        default_value = "6h",
+        env = "BLOCKLIST_REFRESH_INTERVAL",
+        value_parser = parsers::duration(),
+    )]
+    pub blocklist_refresh_interval: Duration,
+    /// Comma-separated list of communities to never follow
+    ///
+    /// Entries are globs matched against the community's `name@instance`, like `news@*`, or
+    /// against its instance when there is no `@`, like `*.example.com`. Entries wrapped in
+    /// slashes, like `/^meta.*@/`, are regular expressions matched against `name@instance`.
+    /// Regular expressions containing commas must be set in the configuration file.
+    #[arg(
+        long,
+        env = "DENY",
+        value_delimiter = ',',
+        value_parser = parsers::pattern(),
+    )]
+    pub deny: Vec<Pattern>,
+    /// Comma-separated list of communities to exclusively follow
+    ///
+    /// When set, only communities matching at least one entry are followed. Uses the same format
+    /// as `--deny`, which takes precedence.
+    #[arg(
+        long,
+        env = "ALLOW",
+        value_delimiter = ',',
+        value_parser = parsers::pattern(),
+    )]
+    pub allow: Vec<Pattern>,
+    /// Comma-separated list of keywords to never follow communities with in their title
+    ///
+    /// Keywords are matched case-insensitively anywhere in the title.
+    #[arg(long, env = "DENY_KEYWORDS", value_delimiter = ',')]
+    pub deny_keywords: Vec<String>,
 
     /// The number of posts to pull from each community
     #[arg(long, default_value_t = 50, env = "POST_COUNT")]
@@ -62,6 +157,9 @@ pub struct Args {
     /// The number of communities to pull from each instance
     #[arg(long, default_value_t = 25, env = "COMMUNITY_COUNT")]
     pub community_count: i32,
+    /// The number of comments to pull from each instance
+    #[arg(long, default_value_t = 50, env = "COMMENT_COUNT")]
+    pub comment_count: i32,
 
     /// A comma-separated list of the methods to sort communities by to find posts
     #[arg(
@@ -72,6 +170,20 @@ pub struct Args {
         value_enum
     )]
     pub sort_methods: Vec<SortType>,
+    /// A comma-separated list of the sources to discover communities from
+    #[arg(
+        long,
+        default_value = "communities,posts",
+        env = "SOURCES",
+        value_delimiter = ',',
+        value_enum
+    )]
+    pub sources: Vec<Source>,
+    /// Whether to exclude, include, or only follow NSFW communities
+    ///
+    /// Applies to both the community listings and the communities of posts.
+    #[arg(long, default_value = "exclude", env = "NSFW", value_enum)]
+    pub nsfw: NsfwPolicy,
 
     /// How long to wait after subscribing to a community
     ///
@@ -98,6 +210,120 @@ pub struct Args {
     )]
     pub run_interval: Duration,
 
+    /// How often to unfollow stale communities
+    ///
+    /// Communities are considered stale if they were removed, hidden, belong to an ignored
+    /// instance or one the local instance doesn't federate with, or have had no new posts within
+    /// `--prune-inactive-after`. Pruning is disabled when unset.
+    #[arg(
+        long,
+        env = "PRUNE_INTERVAL",
+        value_parser = parsers::duration(),
+    )]
+    pub prune_interval: Option<Duration>,
+    /// How long a community can go without new posts before it is pruned
+    ///
+    /// Supports hours, minutes, and seconds unit specifiers with `h`, `m`, and `s` respectively.
+    /// Multiple units can be combined together (i.e `1h30m`). If no units are specified, seconds
+    /// are assumed.
+    #[arg(
+        long,
+        default_value = "720h",
+        env = "PRUNE_INACTIVE_AFTER",
+        value_parser = parsers::duration(),
+    )]
+    pub prune_inactive_after: Duration,
+
+    /// How long a follow can remain pending before it is retried
+    ///
+    /// Remote instances sometimes never accept a follow, leaving it pending forever. Pending follows
+    /// are checked at this interval, and any that are still pending after the timeout are
+    /// unfollowed and followed again. Retrying is disabled when unset.
+    #[arg(
+        long,
+        env = "PENDING_TIMEOUT",
+        value_parser = parsers::duration(),
+    )]
+    pub pending_timeout: Option<Duration>,
+    /// How many times to retry a pending follow before giving up and unfollowing the community
+    #[arg(long, default_value_t = 3, env = "PENDING_MAX_RETRIES")]
+    pub pending_max_retries: u32,
+
+    /// How many times to retry a request that failed transiently before giving up
+    ///
+    /// Requests are retried on network errors, when rate limited, or when the server is
+    /// temporarily unavailable.
+    #[arg(long, default_value_t = 3, env = "MAX_RETRIES")]
+    pub max_retries: u32,
+    /// How long to wait before the first retry, doubling for each subsequent retry
+    ///
+    /// A delay requested by the server with the `Retry-After` header takes precedence. Supports
+    /// hours, minutes, and seconds unit specifiers with `h`, `m`, and `s` respectively.
+    #[arg(
+        long,
+        default_value = "1s",
+        env = "RETRY_BACKOFF",
+        value_parser = parsers::duration(),
+    )]
+    pub retry_backoff: Duration,
+    /// How long a request may take before it is abandoned and retried
+    ///
+    /// Supports hours, minutes, and seconds unit specifiers with `h`, `m`, and `s` respectively.
+    #[arg(
+        long,
+        default_value = "30s",
+        env = "REQUEST_TIMEOUT",
+        value_parser = parsers::duration(),
+    )]
+    pub request_timeout: Duration,
+    /// How long connecting to an instance may take before it is abandoned and retried
+    ///
+    /// Supports hours, minutes, and seconds unit specifiers with `h`, `m`, and `s` respectively.
+    #[arg(
+        long,
+        default_value = "10s",
+        env = "CONNECT_TIMEOUT",
+        value_parser = parsers::duration(),
+    )]
+    pub connect_timeout: Duration,
+
+    /// Preview the changes that would be made without changing any subscriptions
+    ///
+    /// Every populater runs once, then a plan of the communities that would be followed is
+    /// printed, grouped by peer, source, and sort method. Stale communities that would be pruned
+    /// are logged.
+    #[arg(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Where to persist the journal of followed communities and runs
+    ///
+    /// The file is created if it does not exist and is appended to as communities are followed.
+    /// The journal is only kept in memory when unset, so previously followed and pruned
+    /// communities are forgotten on restart.
+    #[arg(long, env = "STATE_FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Where to cache the authentication token between restarts
+    ///
+    /// The token is only kept in memory when unset.
+    #[arg(long, env = "TOKEN_CACHE")]
+    pub token_cache: Option<PathBuf>,
+
+    /// The address to serve metrics and health checks on, i.e. `0.0.0.0:9100`
+    ///
+    /// Prometheus metrics are available at `/metrics`, liveness at `/healthz`, and readiness at
+    /// `/readyz`. The server is disabled when unset.
+    #[arg(long, env = "HTTP_ADDRESS")]
+    pub http_address: Option<SocketAddr>,
+
+    /// The address to serve the admin API on, i.e. `127.0.0.1:9101`
+    ///
+    /// The API can list, trigger, pause, and resume the populaters, and follow communities
+    /// manually. It is unauthenticated, so it should not be exposed publicly. The API is disabled
+    /// when unset.
+    #[arg(long, env = "ADMIN_ADDRESS")]
+    pub admin_address: Option<SocketAddr>,
+
     /// The default level to emit logs at
     ///
     /// Can be overriden for individual components with `--log-targets` or the `LOG_TARGETS`
@@ -114,23 +340,122 @@ pub struct Args {
     /// Filter input format: https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html#directives
     #[arg(long, env = "LOG_TARGETS")]
     pub log_targets: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The raw matches, used to determine where each argument's value came from
+    #[arg(skip)]
+    matches: ArgMatches,
+}
+
+/// What moco should do
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Populate the local instance, the default when no subcommand is given
+    Run {
+        /// Run every populater once, wait for them to complete, then exit
+        ///
+        /// A summary of the runs is printed, and the exit code is non-zero if any of them failed.
+        /// Useful for scheduling moco with cron, systemd timers, or Kubernetes CronJobs instead of
+        /// running it as a daemon.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Follow a community from the local instance
+    Follow {
+        /// The community to follow, in the form `!name@instance`
+        #[arg(value_parser = parsers::community())]
+        community: String,
+    },
+    /// Unfollow a community from the local instance
+    ///
+    /// The community is recorded as pruned so that the populaters won't follow it again.
+    Unfollow {
+        /// The community to unfollow, in the form `!name@instance`
+        #[arg(value_parser = parsers::community())]
+        community: String,
+    },
+    /// List the local account's communities
+    #[command(subcommand)]
+    List(List),
+}
+
+/// What to list
+#[derive(Clone, Copy, Debug, Subcommand)]
+pub enum List {
+    /// The communities the local account follows, including pending follows
+    Subscribed,
+}
+
+impl Args {
+    /// Whether the argument was set on the command line or through the environment
+    pub fn is_explicit(&self, id: &str) -> bool {
+        matches!(
+            self.matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    }
+
+    /// Whether moco should populate the local instance, rather than manage its subscriptions
+    pub fn populates(&self) -> bool {
+        matches!(self.command, None | Some(Command::Run { .. }))
+    }
+
+    /// Whether every populater should run once instead of periodically
+    pub fn once(&self) -> bool {
+        matches!(self.command, Some(Command::Run { once: true }))
+    }
 }
 
 impl fmt::Debug for Args {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Args")
+            .field("config", &self.config)
             .field("url", &display(&self.url))
             .field("username", &self.username)
             .field("password", &"**********")
+            .field(
+                "totp_secret",
+                &UnwrappedOption(self.totp_secret.as_ref().map(|_| "**********")),
+            )
             .field("peers", &self.peers)
             .field("ignored", &self.ignored)
+            .field("blocklists", &self.blocklists)
+            .field(
+                "blocklist_refresh_interval",
+                &self.blocklist_refresh_interval,
+            )
+            .field("deny", &self.deny)
+            .field("allow", &self.allow)
+            .field("deny_keywords", &self.deny_keywords)
             .field("post_count", &self.post_count)
             .field("community_count", &self.community_count)
+            .field("comment_count", &self.comment_count)
             .field("sort_methods", &self.sort_methods)
+            .field("sources", &self.sources)
+            .field("nsfw", &self.nsfw)
             .field("community_add_delay", &self.community_add_delay)
             .field("run_interval", &self.run_interval)
+            .field("prune_interval", &self.prune_interval)
+            .field("prune_inactive_after", &self.prune_inactive_after)
+            .field("pending_timeout", &self.pending_timeout)
+            .field("pending_max_retries", &self.pending_max_retries)
+            .field("max_retries", &self.max_retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("request_timeout", &self.request_timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("dry_run", &self.dry_run)
+            .field("state_file", &UnwrappedOption(self.state_file.as_ref()))
+            .field("token_cache", &UnwrappedOption(self.token_cache.as_ref()))
+            .field("http_address", &UnwrappedOption(self.http_address.as_ref()))
+            .field(
+                "admin_address",
+                &UnwrappedOption(self.admin_address.as_ref()),
+            )
             .field("log_level", &display(self.log_level))
             .field("log_targets", &UnwrappedOption(self.log_targets.as_deref()))
+            .field("command", &UnwrappedOption(self.command.as_ref()))
             .finish()
     }
 }
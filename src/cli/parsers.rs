@@ -1,9 +1,11 @@
+use crate::filter::Pattern;
 use clap::{
     builder::{NonEmptyStringValueParser, StyledStr, TypedValueParser},
     error::{ContextKind, ContextValue, ErrorKind},
     Arg, Command, Error,
 };
 use std::{ffi::OsStr, time::Duration};
+use totp_rs::{Algorithm, Secret, TOTP};
 use url::Host;
 
 /// Parse as a non-empty string
@@ -21,6 +23,21 @@ pub fn domain() -> DomainValueParser {
     DomainValueParser::default()
 }
 
+/// Parse a community in the form `!name@instance`, with or without the leading `!`
+pub fn community() -> CommunityValueParser {
+    CommunityValueParser::default()
+}
+
+/// Parse a community pattern
+pub fn pattern() -> PatternValueParser {
+    PatternValueParser::default()
+}
+
+/// Parse a base32-encoded TOTP secret
+pub fn totp_secret() -> TotpSecretValueParser {
+    TotpSecretValueParser::default()
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct DurationValueParser {
     inner: NonEmptyStringValueParser,
@@ -36,49 +53,50 @@ impl TypedValueParser for DurationValueParser {
         value: &OsStr,
     ) -> Result<Self::Value, Error> {
         let raw = self.inner.parse_ref(cmd, arg, value)?;
+        parse_duration(&raw).map_err(|message| validation_error(cmd, arg, raw, message))
+    }
+}
 
-        if raw == "0" {
-            return Ok(Duration::from_secs(0));
-        }
+/// Parse a duration with support for hours (h), minutes (m), and seconds (s) suffixes
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    if raw == "0" {
+        return Ok(Duration::from_secs(0));
+    }
 
-        let mut chars = raw.chars();
-
-        let mut seconds = 0u64;
-        'outer: loop {
-            let mut n = 0u64;
-            for c in &mut chars {
-                match c {
-                    '0'..='9' => n = n * 10 + (c as u64 - '0' as u64),
-                    c if c.is_whitespace() => {}
-                    'h' => {
-                        seconds += n * 60 * 60;
-                        continue 'outer;
-                    }
-                    'm' => {
-                        seconds += n * 60;
-                        continue 'outer;
-                    }
-                    's' => {
-                        seconds += n;
-                        continue 'outer;
-                    }
-                    _ => {
-                        return Err(validation_error(
-                            cmd,
-                            arg,
-                            raw,
-                            format!("unknown unit {c:?} — valid units are 'h', 'm', and 's'"),
-                        ));
-                    }
+    let mut chars = raw.chars();
+
+    let mut seconds = 0u64;
+    'outer: loop {
+        let mut n = 0u64;
+        for c in &mut chars {
+            match c {
+                '0'..='9' => n = n * 10 + (c as u64 - '0' as u64),
+                c if c.is_whitespace() => {}
+                'h' => {
+                    seconds += n * 60 * 60;
+                    continue 'outer;
+                }
+                'm' => {
+                    seconds += n * 60;
+                    continue 'outer;
+                }
+                's' => {
+                    seconds += n;
+                    continue 'outer;
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown unit {c:?} — valid units are 'h', 'm', and 's'"
+                    ));
                 }
             }
-
-            seconds += n;
-            break;
         }
 
-        Ok(Duration::from_secs(seconds))
+        seconds += n;
+        break;
     }
+
+    Ok(Duration::from_secs(seconds))
 }
 
 #[derive(Clone, Debug, Default)]
@@ -96,20 +114,100 @@ impl TypedValueParser for DomainValueParser {
         value: &OsStr,
     ) -> Result<Self::Value, Error> {
         let raw = self.inner.parse_ref(cmd, arg, value)?;
+        parse_domain(&raw).map_err(|message| validation_error(cmd, arg, raw, message))
+    }
+}
 
-        let host = Host::parse(&raw).map_err(|e| validation_error(cmd, arg, raw.clone(), e))?;
-        match host {
-            Host::Domain(d) => Ok(d),
-            Host::Ipv4(_) | Host::Ipv6(_) => Err(validation_error(
-                cmd,
-                arg,
-                raw,
-                "IP addresses cannot be used for Lemmy servers",
-            )),
+/// Parse a domain, rejecting IP addresses
+pub fn parse_domain(raw: &str) -> Result<String, String> {
+    match Host::parse(raw).map_err(|e| e.to_string())? {
+        Host::Domain(d) => Ok(d),
+        Host::Ipv4(_) | Host::Ipv6(_) => {
+            Err("IP addresses cannot be used for Lemmy servers".to_owned())
         }
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct CommunityValueParser {
+    inner: NonEmptyStringValueParser,
+}
+
+impl TypedValueParser for CommunityValueParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, Error> {
+        let raw = self.inner.parse_ref(cmd, arg, value)?;
+        parse_community(&raw).map_err(|message| validation_error(cmd, arg, raw, message))
+    }
+}
+
+/// Parse a community into the form `name@instance`
+pub fn parse_community(raw: &str) -> Result<String, String> {
+    let raw = raw.strip_prefix('!').unwrap_or(raw);
+    let (name, instance) = raw
+        .split_once('@')
+        .ok_or_else(|| "community must be in the form !name@instance".to_owned())?;
+    if name.is_empty() {
+        return Err("community name cannot be empty".to_owned());
+    }
+
+    let instance = parse_domain(instance)?;
+    Ok(format!("{name}@{instance}"))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PatternValueParser {
+    inner: NonEmptyStringValueParser,
+}
+
+impl TypedValueParser for PatternValueParser {
+    type Value = Pattern;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, Error> {
+        let raw = self.inner.parse_ref(cmd, arg, value)?;
+        Pattern::parse(&raw).map_err(|error| validation_error(cmd, arg, raw, error))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TotpSecretValueParser {
+    inner: NonEmptyStringValueParser,
+}
+
+impl TypedValueParser for TotpSecretValueParser {
+    type Value = TOTP;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, Error> {
+        let raw = self.inner.parse_ref(cmd, arg, value)?;
+        parse_totp_secret(&raw).map_err(|message| validation_error(cmd, arg, raw, message))
+    }
+}
+
+/// Parse a base32-encoded TOTP secret using the same parameters as Lemmy
+pub fn parse_totp_secret(raw: &str) -> Result<TOTP, String> {
+    let secret = Secret::Encoded(raw.replace(' ', "").to_uppercase())
+        .to_bytes()
+        .map_err(|_| "secret must be base32-encoded".to_owned())?;
+
+    TOTP::new(Algorithm::SHA1, 6, 1, 30, secret).map_err(|e| e.to_string())
+}
+
 fn validation_error(
     cmd: &Command,
     arg: Option<&Arg>,
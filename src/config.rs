@@ -0,0 +1,416 @@
+use crate::{
+    api::SortType,
+    cli::{self, Args},
+    filter::{Pattern, Rules},
+    populater::{NsfwPolicy, Source},
+};
+use clap::ValueEnum;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+#[cfg(unix)]
+use tokio::signal::unix::{self, Signal, SignalKind};
+use tokio::time;
+use tracing::{debug, instrument};
+
+/// How often to check the configuration file for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The contents of the configuration file
+///
+/// Settings in the `[defaults]` table apply to every peer, but are overridden by command line
+/// arguments and environment variables. Settings in a `[peers."<domain>"]` table only apply to
+/// that peer and take precedence over everything else.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Domains to ignore posts from, in addition to those from the arguments
+    #[serde(default)]
+    pub ignored: Vec<String>,
+    /// Communities to never follow, in addition to those from the arguments
+    #[serde(default, deserialize_with = "patterns")]
+    pub deny: Vec<Pattern>,
+    /// Communities to exclusively follow, in addition to those from the arguments
+    #[serde(default, deserialize_with = "patterns")]
+    pub allow: Vec<Pattern>,
+    /// Keywords to never follow communities with in their title, in addition to those from the
+    /// arguments
+    #[serde(default)]
+    pub deny_keywords: Vec<String>,
+    #[serde(default)]
+    defaults: Overrides,
+    #[serde(default)]
+    peers: BTreeMap<String, Overrides>,
+}
+
+/// Settings that can be overridden by the configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    #[serde(default, deserialize_with = "sort_methods")]
+    sort_methods: Option<Vec<SortType>>,
+    post_count: Option<i32>,
+    community_count: Option<i32>,
+    comment_count: Option<i32>,
+    #[serde(default, deserialize_with = "duration")]
+    run_interval: Option<Duration>,
+    nsfw: Option<NsfwPolicy>,
+    sources: Option<Vec<Source>>,
+}
+
+impl Config {
+    /// Load the configuration from a TOML file
+    #[instrument(name = "Config::load", fields(path = %path.display()))]
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        let config = toml::from_str::<Config>(&contents)?;
+
+        for domain in config.peers.keys() {
+            cli::parse_domain(domain)
+                .map_err(|reason| ConfigError::InvalidPeer(domain.clone(), reason))?;
+        }
+        for domain in &config.ignored {
+            cli::parse_domain(domain)
+                .map_err(|reason| ConfigError::InvalidIgnored(domain.clone(), reason))?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// Watches for requests to reload the configuration
+///
+/// A reload is requested when the configuration file is modified or, on Unix, when the process
+/// receives `SIGHUP`.
+pub struct Watcher {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    hangup: Signal,
+}
+
+impl Watcher {
+    /// Watch the configuration file, if there is one
+    pub fn new(path: Option<&Path>) -> io::Result<Watcher> {
+        Ok(Watcher {
+            path: path.map(Path::to_path_buf),
+            modified: path.and_then(modified),
+            #[cfg(unix)]
+            hangup: unix::signal(SignalKind::hangup())?,
+        })
+    }
+
+    /// Wait until a reload is requested
+    pub async fn changed(&mut self) {
+        #[cfg(unix)]
+        let hangup = self.hangup.recv();
+        #[cfg(not(unix))]
+        let hangup = futures::future::pending::<()>();
+
+        let (path, last_modified) = (&self.path, &mut self.modified);
+        let file = async move {
+            let Some(path) = path else {
+                return futures::future::pending().await;
+            };
+
+            loop {
+                time::sleep(WATCH_INTERVAL).await;
+
+                let current = modified(path);
+                if current != *last_modified {
+                    *last_modified = current;
+                    break;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = hangup => debug!("received SIGHUP"),
+            _ = file => debug!("configuration file changed"),
+        }
+    }
+}
+
+/// When the file was last modified
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The resolved settings for a peer
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerSettings {
+    pub domain: String,
+    pub sort_methods: Vec<SortType>,
+    pub post_count: i32,
+    pub community_count: i32,
+    pub comment_count: i32,
+    pub run_interval: Duration,
+    pub nsfw: NsfwPolicy,
+    pub sources: Vec<Source>,
+}
+
+impl PeerSettings {
+    /// The number of items to fetch from the source
+    pub fn limit(&self, source: Source) -> i32 {
+        match source {
+            Source::Communities => self.community_count,
+            Source::Posts | Source::Trending => self.post_count,
+            Source::Comments => self.comment_count,
+        }
+    }
+}
+
+/// Merge the command line arguments with the configuration file to get the settings for each peer
+///
+/// Peers listed in either the arguments or the configuration file are included.
+pub fn resolve(args: &Args, config: &Config) -> Vec<PeerSettings> {
+    let mut domains = args.peers.clone();
+    for domain in config.peers.keys() {
+        if !domains.contains(domain) {
+            domains.push(domain.clone());
+        }
+    }
+
+    let defaults = &config.defaults;
+    let empty = Overrides::default();
+
+    domains
+        .into_iter()
+        .map(|domain| {
+            let peer = config.peers.get(&domain).unwrap_or(&empty);
+
+            macro_rules! pick {
+                ($field:ident) => {
+                    match (&peer.$field, &defaults.$field) {
+                        (Some(value), _) => value.clone(),
+                        (None, Some(value)) if !args.is_explicit(stringify!($field)) => {
+                            value.clone()
+                        }
+                        _ => args.$field.clone(),
+                    }
+                };
+            }
+
+            PeerSettings {
+                sort_methods: pick!(sort_methods),
+                post_count: pick!(post_count),
+                community_count: pick!(community_count),
+                comment_count: pick!(comment_count),
+                run_interval: pick!(run_interval),
+                nsfw: pick!(nsfw),
+                sources: pick!(sources),
+                domain,
+            }
+        })
+        .collect()
+}
+
+/// Merge the command line arguments with the configuration file to get the community filter rules
+pub fn rules(args: &Args, config: &Config) -> Rules {
+    Rules {
+        allow: args.allow.iter().chain(&config.allow).cloned().collect(),
+        deny: args.deny.iter().chain(&config.deny).cloned().collect(),
+        deny_keywords: (args.deny_keywords.iter())
+            .chain(&config.deny_keywords)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Deserialize community patterns using the same format as the command line
+fn patterns<'de, D>(deserializer: D) -> Result<Vec<Pattern>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|raw| Pattern::parse(raw).map_err(D::Error::custom))
+        .collect()
+}
+
+/// Deserialize sort methods using the same names as the command line
+fn sort_methods<'de, D>(deserializer: D) -> Result<Option<Vec<SortType>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(raw) = Option::<Vec<String>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    raw.iter()
+        .map(|method| SortType::from_str(method, true).map_err(D::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Deserialize a duration using the same format as the command line
+fn duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(raw) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    cli::parse_duration(&raw)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// Errors that can occur while loading the configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Read(io::Error),
+    /// The file is not valid TOML or contains unknown settings
+    Parse(toml::de::Error),
+    /// A peer's domain is invalid
+    InvalidPeer(String, String),
+    /// An ignored domain is invalid
+    InvalidIgnored(String, String),
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read(err) => Some(err),
+            Self::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(_) => write!(f, "could not read the configuration file"),
+            Self::Parse(_) => write!(f, "invalid configuration file"),
+            Self::InvalidPeer(domain, reason) => write!(f, "invalid peer {domain:?}: {reason}"),
+            Self::InvalidIgnored(domain, reason) => {
+                write!(f, "invalid ignored domain {domain:?}: {reason}")
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        Self::Read(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        Self::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, Config, PeerSettings};
+    use crate::cli;
+
+    /// Resolve the settings for each peer from the arguments and inline configuration
+    fn settings(args: &[&str], config: &str) -> Vec<PeerSettings> {
+        let args = cli::try_parse_from(
+            ["moco", "--username", "bot", "--password", "pw"]
+                .iter()
+                .chain(args),
+        )
+        .expect("arguments must be valid");
+        let config = toml::from_str::<Config>(config).expect("configuration must be valid");
+
+        resolve(&args, &config)
+    }
+
+    fn post_count(settings: &[PeerSettings], domain: &str) -> i32 {
+        settings
+            .iter()
+            .find(|s| s.domain == domain)
+            .expect("peer must be resolved")
+            .post_count
+    }
+
+    #[test]
+    fn built_in_default() {
+        let settings = settings(&["--peers", "a.test"], "");
+        assert_eq!(post_count(&settings, "a.test"), 50);
+    }
+
+    #[test]
+    fn defaults_table_over_built_in() {
+        let settings = settings(&["--peers", "a.test"], "[defaults]\npost_count = 10");
+        assert_eq!(post_count(&settings, "a.test"), 10);
+    }
+
+    #[test]
+    fn arguments_over_defaults_table() {
+        let settings = settings(
+            &["--peers", "a.test", "--post-count", "20"],
+            "[defaults]\npost_count = 10",
+        );
+        assert_eq!(post_count(&settings, "a.test"), 20);
+    }
+
+    #[test]
+    fn environment_over_defaults_table() {
+        // No other test checks the comment count, so setting it doesn't affect them
+        std::env::set_var("COMMENT_COUNT", "40");
+        let settings = settings(&["--peers", "a.test"], "[defaults]\ncomment_count = 10");
+        std::env::remove_var("COMMENT_COUNT");
+
+        assert_eq!(settings[0].comment_count, 40);
+    }
+
+    #[test]
+    fn arguments_matching_the_built_in_default_are_explicit() {
+        let settings = settings(
+            &["--peers", "a.test", "--post-count", "50"],
+            "[defaults]\npost_count = 10",
+        );
+        assert_eq!(post_count(&settings, "a.test"), 50);
+    }
+
+    #[test]
+    fn peer_table_over_everything() {
+        let settings = settings(
+            &["--peers", "a.test,b.test", "--post-count", "20"],
+            r#"
+                [defaults]
+                post_count = 10
+
+                [peers."a.test"]
+                post_count = 30
+            "#,
+        );
+        assert_eq!(post_count(&settings, "a.test"), 30);
+        assert_eq!(post_count(&settings, "b.test"), 20);
+    }
+
+    #[test]
+    fn peers_only_in_configuration_are_included() {
+        let settings = settings(
+            &["--peers", "a.test"],
+            r#"
+                [defaults]
+                post_count = 10
+
+                [peers."b.test"]
+                community_count = 5
+            "#,
+        );
+
+        let domains = settings
+            .iter()
+            .map(|s| s.domain.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(domains, ["a.test", "b.test"]);
+        assert_eq!(post_count(&settings, "b.test"), 10);
+        assert_eq!(settings[1].community_count, 5);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    api::{FetchError, LemmyApi},
+    populater::periodically,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{error, info, info_span, Instrument};
+
+/// The local instance's federation policy, refreshed periodically
+///
+/// Every clone sees the latest policy at once.
+#[derive(Clone, Default)]
+pub struct Federation {
+    policy: Arc<RwLock<Policy>>,
+}
+
+#[derive(Default)]
+struct Policy {
+    /// The local site's domain, which can differ from the host its API is reached through
+    local: String,
+    blocked: HashSet<String>,
+    allowed: HashSet<String>,
+}
+
+impl Federation {
+    /// Check whether the local instance federates with the instance, returning why not if it doesn't
+    ///
+    /// When the local instance uses an allowlist, only the allowed instances and itself are
+    /// permitted.
+    pub fn rejects(&self, instance: &str) -> Option<&'static str> {
+        let policy = self.policy.read().expect("federation lock poisoned");
+        if policy.blocked.contains(instance) {
+            Some("blocked by local instance")
+        } else if !policy.allowed.is_empty()
+            && instance != policy.local
+            && !policy.allowed.contains(instance)
+        {
+            Some("not allowed by local instance")
+        } else {
+            None
+        }
+    }
+}
+
+/// Shared context passed through to the federation policy refresher
+#[derive(Clone)]
+pub struct Context {
+    local: LemmyApi,
+    federation: Federation,
+}
+
+/// Create a new context for the federation policy refresher
+pub fn context(local: LemmyApi, federation: Federation) -> Context {
+    Context { local, federation }
+}
+
+/// Periodically refresh the local instance's federation policy
+pub async fn launch(context: Context, interval: Duration, stop: broadcast::Receiver<()>) {
+    info!("federation policy refresher started");
+
+    periodically(interval, stop, || run(&context)).await;
+
+    info!("federation policy refresher halted");
+}
+
+/// Refresh the local instance's federation policy once, keeping the previous policy on failure
+pub async fn run(context: &Context) {
+    async {
+        let fetched = async {
+            let site = context.local.site().await?;
+            let federated = context.local.federated_instances().await?;
+            Ok::<_, FetchError>((site, federated))
+        };
+
+        let (site, federated) = match fetched.await {
+            Ok(fetched) => fetched,
+            Err(error) => {
+                error!(
+                    error = &error as &(dyn std::error::Error + 'static),
+                    "failed to fetch federation policy, keeping previous policy"
+                );
+                return;
+            }
+        };
+
+        let policy = Policy {
+            local: site.actor_id.host_str().unwrap_or_default().to_owned(),
+            blocked: federated.blocked.into_iter().map(|i| i.domain).collect(),
+            allowed: federated.allowed.into_iter().map(|i| i.domain).collect(),
+        };
+        info!(
+            local = %policy.local,
+            blocked = policy.blocked.len(),
+            allowed = policy.allowed.len(),
+            "complete"
+        );
+
+        *context
+            .federation
+            .policy
+            .write()
+            .expect("federation lock poisoned") = policy;
+    }
+    .instrument(info_span!("federation"))
+    .await
+}
@@ -0,0 +1,138 @@
+use regex::{Regex, RegexBuilder};
+use std::{
+    fmt::{self, Formatter},
+    sync::{Arc, RwLock},
+};
+
+/// Community-level rules deciding which communities can be followed
+///
+/// The rules can be replaced while running, with every clone seeing the new rules at once.
+#[derive(Clone, Default)]
+pub struct CommunityFilter {
+    rules: Arc<RwLock<Rules>>,
+}
+
+/// The rules a community is checked against
+///
+/// Deny rules take precedence over allow rules. When there are allow rules, only communities
+/// matching at least one of them can be followed.
+#[derive(Debug, Default)]
+pub struct Rules {
+    pub allow: Vec<Pattern>,
+    pub deny: Vec<Pattern>,
+    pub deny_keywords: Vec<String>,
+}
+
+impl CommunityFilter {
+    /// Create a new filter from the rules
+    pub fn new(rules: Rules) -> CommunityFilter {
+        CommunityFilter {
+            rules: Arc::new(RwLock::new(rules.normalized())),
+        }
+    }
+
+    /// Check the community, returning the rule that rejected it if there is one
+    pub fn rejects(&self, name: &str, instance: &str, title: &str) -> Option<String> {
+        let rules = self.rules.read().expect("filter lock poisoned");
+
+        if let Some(pattern) = rules.deny.iter().find(|p| p.matches(name, instance)) {
+            return Some(format!("deny {pattern}"));
+        }
+
+        let title = title.to_lowercase();
+        if let Some(keyword) = rules.deny_keywords.iter().find(|k| title.contains(*k)) {
+            return Some(format!("deny keyword {keyword:?}"));
+        }
+
+        if !rules.allow.is_empty() && !rules.allow.iter().any(|p| p.matches(name, instance)) {
+            return Some("no matching allow rule".to_owned());
+        }
+
+        None
+    }
+
+    /// Atomically replace the rules
+    pub fn replace(&self, rules: Rules) {
+        *self.rules.write().expect("filter lock poisoned") = rules.normalized();
+    }
+}
+
+impl Rules {
+    /// Lowercase the keywords so they can be matched case-insensitively
+    fn normalized(mut self) -> Rules {
+        for keyword in &mut self.deny_keywords {
+            *keyword = keyword.to_lowercase();
+        }
+        self.deny_keywords.retain(|k| !k.is_empty());
+        self
+    }
+}
+
+/// A pattern matching communities
+///
+/// Patterns are globs, where `*` matches any number of characters and `?` matches a single
+/// character, or regular expressions when wrapped in slashes. Globs containing an `@` and regular
+/// expressions are matched against the community's `name@instance`, while other globs are
+/// matched against its instance. Matching is case-insensitive.
+#[derive(Clone)]
+pub struct Pattern {
+    raw: String,
+    regex: Regex,
+    instance_only: bool,
+}
+
+impl Pattern {
+    /// Parse a glob or a regular expression wrapped in slashes
+    pub fn parse(raw: &str) -> Result<Pattern, regex::Error> {
+        let (expression, instance_only) =
+            match raw.strip_prefix('/').and_then(|r| r.strip_suffix('/')) {
+                Some(expression) if !expression.is_empty() => (expression.to_owned(), false),
+                _ => (glob_to_regex(raw), !raw.contains('@')),
+            };
+
+        let regex = RegexBuilder::new(&expression)
+            .case_insensitive(true)
+            .build()?;
+
+        Ok(Pattern {
+            raw: raw.to_owned(),
+            regex,
+            instance_only,
+        })
+    }
+
+    /// Whether the community, given by its `name@instance`, matches the pattern
+    fn matches(&self, name: &str, instance: &str) -> bool {
+        if self.instance_only {
+            self.regex.is_match(instance)
+        } else {
+            self.regex.is_match(name)
+        }
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.raw, f)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.raw, f)
+    }
+}
+
+/// Convert a glob into an anchored regular expression
+fn glob_to_regex(glob: &str) -> String {
+    let mut expression = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    expression
+}
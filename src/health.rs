@@ -0,0 +1,97 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How many run intervals a populater can go without completing a run before it is unhealthy
+const MISSED_RUNS: u32 = 3;
+
+/// Tracks whether moco is ready to serve and whether its populaters are still running
+///
+/// Every clone shares the same state.
+#[derive(Clone, Default)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    ready: AtomicBool,
+    next_id: AtomicU64,
+    populaters: Mutex<HashMap<u64, Populater>>,
+}
+
+/// The last known state of a populater
+struct Populater {
+    name: String,
+    interval: Duration,
+    last_run: Instant,
+}
+
+impl Health {
+    /// Mark whether moco is ready, i.e. it has logged in to the local instance
+    pub fn set_ready(&self, ready: bool) {
+        self.inner.ready.store(ready, Ordering::Relaxed);
+    }
+
+    /// Whether moco is ready
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::Relaxed)
+    }
+
+    /// Start tracking a populater that is expected to complete a run every interval
+    ///
+    /// The populater stops being tracked once the returned heartbeat is dropped.
+    pub fn register(&self, name: String, interval: Duration) -> Heartbeat {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let populater = Populater {
+            name,
+            interval,
+            last_run: Instant::now(),
+        };
+        self.populaters().insert(id, populater);
+
+        Heartbeat {
+            id,
+            health: self.clone(),
+        }
+    }
+
+    /// Get the populaters that have not completed a run recently enough
+    pub fn stalled(&self) -> Vec<String> {
+        self.populaters()
+            .values()
+            .filter(|p| p.last_run.elapsed() > p.interval * MISSED_RUNS)
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    fn populaters(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Populater>> {
+        self.inner.populaters.lock().expect("health lock poisoned")
+    }
+}
+
+/// Records the runs of a single populater
+pub struct Heartbeat {
+    id: u64,
+    health: Health,
+}
+
+impl Heartbeat {
+    /// Record that the populater completed a run
+    pub fn beat(&self) {
+        if let Some(populater) = self.health.populaters().get_mut(&self.id) {
+            populater.last_run = Instant::now();
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.health.populaters().remove(&self.id);
+    }
+}
@@ -0,0 +1,42 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+/// The set of instances to ignore communities from
+///
+/// The set is made up of the configured instances and those imported from blocklists, either of
+/// which can be replaced while running, with every clone seeing the new set at once.
+#[derive(Clone, Default)]
+pub struct Ignored {
+    instances: Arc<RwLock<HashSet<String>>>,
+    imported: Arc<RwLock<HashSet<String>>>,
+}
+
+impl Ignored {
+    /// Create a new set of ignored instances
+    pub fn new(instances: impl IntoIterator<Item = String>) -> Ignored {
+        Ignored {
+            instances: Arc::new(RwLock::new(instances.into_iter().collect())),
+            imported: Arc::default(),
+        }
+    }
+
+    /// Check whether the instance is ignored
+    pub fn contains(&self, instance: &str) -> bool {
+        let instances = self.instances.read().expect("ignored lock poisoned");
+        let imported = self.imported.read().expect("ignored lock poisoned");
+        instances.contains(instance) || imported.contains(instance)
+    }
+
+    /// Atomically replace the configured instances
+    pub fn replace(&self, instances: impl IntoIterator<Item = String>) {
+        let instances = instances.into_iter().collect();
+        *self.instances.write().expect("ignored lock poisoned") = instances;
+    }
+
+    /// Atomically replace the instances imported from blocklists
+    pub fn replace_imported(&self, instances: HashSet<String>) {
+        *self.imported.write().expect("ignored lock poisoned") = instances;
+    }
+}
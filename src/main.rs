@@ -1,16 +1,40 @@
-use eyre::WrapErr;
-use std::{collections::HashSet, sync::Arc};
-use tokio::{signal, sync::broadcast};
-use tracing::{debug, info};
-use url::Url;
+use eyre::{eyre, WrapErr};
+use tokio::{
+    signal,
+    sync::{broadcast, mpsc},
+};
+use tracing::{debug, error, info, instrument, warn};
 
+mod admin;
 mod api;
+mod blocklist;
 mod cli;
+mod config;
+mod federation;
+mod filter;
+mod health;
+mod ignored;
 mod logging;
+mod manage;
+mod metrics;
+mod pending;
 mod populater;
+mod pruner;
+mod server;
+mod state;
+mod supervisor;
+mod table;
 
-use api::LemmyApi;
-use populater::{FromCommunities, FromPosts};
+use api::{LemmyApi, RetryPolicy, Timeouts};
+use cli::{Args, Command, List};
+use config::Config;
+use federation::Federation;
+use filter::CommunityFilter;
+use health::Health;
+use ignored::Ignored;
+use populater::{Plan, Report};
+use state::State;
+use supervisor::Supervisor;
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -23,69 +47,204 @@ async fn main() -> eyre::Result<()> {
     }
 
     let args = cli::parse();
-    let ignored = Arc::new(args.ignored.clone().into_iter().collect::<HashSet<_>>());
 
     logging::init(args.log_level, args.log_targets.as_deref());
 
     debug!(?args);
 
-    let mut client = LemmyApi::connect(&args.url)
+    let config = match &args.config {
+        Some(path) => Config::load(path).wrap_err("failed to load configuration")?,
+        None => Config::default(),
+    };
+    let peers = config::resolve(&args, &config);
+    debug!(?peers);
+
+    let ignored = Ignored::new(args.ignored.iter().chain(&config.ignored).cloned());
+    let filter = CommunityFilter::new(config::rules(&args, &config));
+    let state = match &args.state_file {
+        Some(path) => State::open(path).wrap_err("failed to load state")?,
+        None => {
+            warn!("no state file configured, followed communities will be forgotten on restart");
+            State::in_memory()
+        }
+    };
+
+    let health = Health::default();
+    let (stop, _) = broadcast::channel(1);
+    let mut tasks = Vec::new();
+    if let Some(address) = args.http_address.filter(|_| args.populates()) {
+        let server = server::observability(address, health.clone(), stop.subscribe())
+            .wrap_err("failed to start http server")?;
+        tasks.push(tokio::task::spawn(server));
+    }
+
+    let retry = RetryPolicy {
+        max_retries: args.max_retries,
+        backoff: args.retry_backoff,
+    };
+    let timeouts = Timeouts {
+        request: args.request_timeout,
+        connect: args.connect_timeout,
+    };
+
+    let mut client = LemmyApi::connect(&args.url, timeouts)
         .await
-        .wrap_err("connection to instance failed")?;
-    debug!(instance = %args.url, "connected to the local instance");
+        .wrap_err("connection to instance failed")?
+        .with_retry_policy(retry);
+    if let Some(path) = &args.token_cache {
+        client = client.with_token_cache(path.clone());
+    }
+    debug!(instance = %args.url, version = %client.version(), "connected to the local instance");
 
     client
-        .login(&args.username, &args.password)
+        .login(&args.username, &args.password, args.totp_secret.clone())
         .await
         .wrap_err("login failed")?;
 
     info!(instance = %args.url, "successfully logged in");
+    health.set_ready(true);
 
-    let (stop, _) = broadcast::channel(1);
+    match &args.command {
+        Some(Command::Follow { community }) => {
+            return manage::follow(&client, &state, community, args.dry_run).await;
+        }
+        Some(Command::Unfollow { community }) => {
+            return manage::unfollow(&client, &state, community, args.dry_run).await;
+        }
+        Some(Command::List(List::Subscribed)) => return manage::list_subscribed(&client).await,
+        None | Some(Command::Run { .. }) => {}
+    }
 
-    let mut tasks = Vec::with_capacity(args.peers.len() * args.sort_methods.len());
-    for peer in args.peers {
-        let url = Url::parse(&format!("https://{peer}"))
-            .wrap_err_with(|| format!("could not build URL for {peer}"))?;
+    let federation = Federation::default();
+    let federation_refresher = federation::context(client.clone(), federation.clone());
+    federation::run(&federation_refresher).await;
 
-        let peer = LemmyApi::connect(&url)
-            .await
-            .wrap_err_with(|| format!("cannot connect to peer {peer}"))?;
+    let plan = args.dry_run.then(Plan::default);
+    let shared = populater::Shared {
+        local: client.clone(),
+        ignored: ignored.clone(),
+        federation: federation.clone(),
+        filter: filter.clone(),
+        add_delay: args.community_add_delay,
+        state: state.clone(),
+        plan: plan.clone(),
+        health: health.clone(),
+    };
+    let mut supervisor = Supervisor::new(shared, retry, timeouts);
 
-        let context = populater::context(
+    let blocklists = (!args.blocklists.is_empty())
+        .then(|| blocklist::context(ignored.clone(), args.blocklists.clone()));
+    if let Some(context) = &blocklists {
+        blocklist::run(context).await;
+    }
+
+    let pruner = args.prune_interval.map(|interval| {
+        let context = pruner::context(
             client.clone(),
-            peer,
             ignored.clone(),
+            federation.clone(),
+            args.prune_inactive_after,
             args.community_add_delay,
+            args.dry_run,
+            state.clone(),
         );
+        (context, interval)
+    });
+    let pending = args.pending_timeout.map(|timeout| {
+        pending::context(
+            client.clone(),
+            timeout,
+            args.pending_max_retries,
+            args.community_add_delay,
+            args.dry_run,
+            state.clone(),
+        )
+    });
 
-        for method in &args.sort_methods {
-            let communities = tokio::task::spawn(populater::launch::<FromCommunities>(
-                context.clone(),
-                *method,
-                args.community_count,
-                args.run_interval,
-                stop.subscribe(),
-            ));
-
-            let posts = tokio::task::spawn(populater::launch::<FromPosts>(
-                context.clone(),
-                *method,
-                args.post_count,
-                args.run_interval,
-                stop.subscribe(),
-            ));
-
-            tasks.push(communities);
-            tasks.push(posts);
+    if let Some(plan) = plan {
+        info!("dry run, waiting for populaters to complete...");
+        supervisor.run_once(&peers).await;
+        if let Some((context, _)) = pruner {
+            pruner::run(&context).await;
+        }
+        if let Some(context) = pending {
+            pending::run(&context).await;
         }
+
+        print!("{plan}");
+        return Ok(());
     }
 
-    wait_for_terminate().await;
+    if args.once() {
+        info!("running once, waiting for populaters to complete...");
+        let report = Report::new(supervisor.run_once(&peers).await);
+        if let Some((context, _)) = pruner {
+            pruner::run(&context).await;
+        }
+        if let Some(context) = pending {
+            pending::run(&context).await;
+        }
+
+        print!("{report}");
+        return match report.failures() {
+            0 => Ok(()),
+            failures => Err(eyre!("{failures} populations failed")),
+        };
+    }
+
+    supervisor.reconcile(&peers).await?;
+
+    if let Some((context, interval)) = pruner {
+        tasks.push(tokio::task::spawn(pruner::launch(
+            context,
+            interval,
+            stop.subscribe(),
+        )));
+    }
+    if let Some(context) = pending {
+        tasks.push(tokio::task::spawn(pending::launch(
+            context,
+            stop.subscribe(),
+        )));
+    }
+    tasks.push(tokio::task::spawn(federation::launch(
+        federation_refresher,
+        args.blocklist_refresh_interval,
+        stop.subscribe(),
+    )));
+    if let Some(context) = blocklists {
+        tasks.push(tokio::task::spawn(blocklist::launch(
+            context,
+            args.blocklist_refresh_interval,
+            stop.subscribe(),
+        )));
+    }
 
-    stop.send(()).wrap_err("populaters stopped unexpectedly")?;
+    let (admin, mut admin_requests) = mpsc::channel(16);
+    if let Some(address) = args.admin_address {
+        let server =
+            admin::serve(address, admin, stop.subscribe()).wrap_err("failed to start admin api")?;
+        tasks.push(tokio::task::spawn(server));
+    }
+
+    let mut watcher = config::Watcher::new(args.config.as_deref())
+        .wrap_err("failed to watch for configuration changes")?;
+
+    let terminate = wait_for_terminate();
+    tokio::pin!(terminate);
+    loop {
+        tokio::select! {
+            _ = &mut terminate => break,
+            _ = watcher.changed() => reload(&args, &ignored, &filter, &mut supervisor).await,
+            Some(request) = admin_requests.recv() => admin::respond(request, &supervisor),
+        }
+    }
+
+    // The background tasks might not be running, so there may be no receivers
+    let _ = stop.send(());
 
     info!("waiting for populaters to exit...");
+    supervisor.shutdown().await;
     futures::future::join_all(tasks).await;
 
     info!("successfully shutdown");
@@ -94,6 +253,41 @@ async fn main() -> eyre::Result<()> {
     Ok(())
 }
 
+/// Reload the configuration and reconcile the running populaters
+#[instrument(skip_all)]
+async fn reload(
+    args: &Args,
+    ignored: &Ignored,
+    filter: &CommunityFilter,
+    supervisor: &mut Supervisor,
+) {
+    info!("reloading configuration");
+
+    let config = match &args.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(error) => {
+                error!(
+                    error = &error as &(dyn std::error::Error + 'static),
+                    "invalid configuration, keeping current settings"
+                );
+                return;
+            }
+        },
+        None => Config::default(),
+    };
+
+    ignored.replace(args.ignored.iter().chain(&config.ignored).cloned());
+    filter.replace(config::rules(args, &config));
+
+    let peers = config::resolve(args, &config);
+    debug!(?peers);
+
+    if let Err(error) = supervisor.reconcile(&peers).await {
+        error!(?error, "failed to start some populaters");
+    }
+}
+
 async fn wait_for_terminate() {
     let ctrl_c = async {
         signal::ctrl_c()
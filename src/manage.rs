@@ -0,0 +1,195 @@
+use crate::{
+    api::{CommunityView, LemmyApi, SubscribedType},
+    populater::MANUAL_SOURCE,
+    state::{Event, Followed, State, Unfollowed},
+    table::column_width,
+};
+use chrono::Utc;
+use eyre::{eyre, WrapErr};
+use std::fmt::{self, Formatter};
+use tracing::instrument;
+
+/// The reason recorded for communities that were unfollowed manually
+const MANUAL_REASON: &str = "unfollowed manually";
+
+/// Follow the community, in the form `name@instance`, from the local instance
+#[instrument(name = "manage::follow", skip(local, state))]
+pub async fn follow(
+    local: &LemmyApi,
+    state: &State,
+    community: &str,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let view = resolve(local, community).await?;
+    let name = qualified_name(&view);
+    if view.subscribed != SubscribedType::NotSubscribed {
+        println!("already following {name}");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("would follow {name}");
+        return Ok(());
+    }
+
+    local
+        .follow_community(view.community.id)
+        .await
+        .wrap_err("failed to follow community")?;
+
+    let instance = view.community.actor_id.host_str().unwrap_or_default();
+    state.record_or_warn(Event::Followed(Followed {
+        name: name.clone(),
+        peer: instance.to_owned(),
+        actor_id: view.community.actor_id,
+        source: MANUAL_SOURCE.to_owned(),
+        sort: None,
+        at: Utc::now(),
+    }));
+
+    println!("followed {name}");
+    Ok(())
+}
+
+/// Unfollow the community, in the form `name@instance`, from the local instance
+///
+/// The community is recorded as pruned so the populaters don't follow it again.
+#[instrument(name = "manage::unfollow", skip(local, state))]
+pub async fn unfollow(
+    local: &LemmyApi,
+    state: &State,
+    community: &str,
+    dry_run: bool,
+) -> eyre::Result<()> {
+    let view = resolve(local, community).await?;
+    let name = qualified_name(&view);
+    if view.subscribed == SubscribedType::NotSubscribed {
+        println!("not following {name}");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("would unfollow {name}");
+        return Ok(());
+    }
+
+    local
+        .unfollow_community(view.community.id)
+        .await
+        .wrap_err("failed to unfollow community")?;
+
+    state.record_or_warn(Event::Unfollowed(Unfollowed {
+        name: name.clone(),
+        actor_id: view.community.actor_id,
+        reason: MANUAL_REASON.to_owned(),
+        at: Utc::now(),
+    }));
+
+    println!("unfollowed {name}");
+    Ok(())
+}
+
+/// Print the communities the local account follows
+#[instrument(name = "manage::list_subscribed", skip_all)]
+pub async fn list_subscribed(local: &LemmyApi) -> eyre::Result<()> {
+    let subscriptions = local
+        .list_subscribed()
+        .await
+        .wrap_err("failed to list subscriptions")?;
+
+    print!("{}", Subscriptions::new(subscriptions));
+    Ok(())
+}
+
+/// Look up the community through the local instance, fetching it if it hasn't federated yet
+///
+/// The community is resolved by its `!name@instance` handle rather than a guessed actor id, since
+/// not every platform serves communities under `/c/`.
+async fn resolve(local: &LemmyApi, community: &str) -> eyre::Result<CommunityView> {
+    local
+        .resolve_object(&format!("!{community}"))
+        .await
+        .wrap_err("failed to resolve community")?
+        .ok_or_else(|| eyre!("community {community} does not exist"))
+}
+
+/// The community's name in the form `name@instance`, as the server knows it
+fn qualified_name(view: &CommunityView) -> String {
+    let instance = view.community.actor_id.host_str().unwrap_or_default();
+    format!("{}@{instance}", view.community.name)
+}
+
+/// The communities the local account follows
+struct Subscriptions {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    instance: String,
+    nsfw: bool,
+    state: &'static str,
+    title: String,
+}
+
+impl Subscriptions {
+    fn new(views: Vec<CommunityView>) -> Subscriptions {
+        let mut entries = views
+            .into_iter()
+            .map(|view| Entry {
+                instance: view
+                    .community
+                    .actor_id
+                    .host_str()
+                    .unwrap_or_default()
+                    .to_owned(),
+                name: view.community.name,
+                nsfw: view.community.nsfw,
+                state: match view.subscribed {
+                    SubscribedType::Subscribed => "subscribed",
+                    SubscribedType::Pending => "pending",
+                    SubscribedType::NotSubscribed => "not subscribed",
+                },
+                title: view.community.title,
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| (&a.instance, &a.name).cmp(&(&b.instance, &b.name)));
+        Subscriptions { entries }
+    }
+}
+
+impl fmt::Display for Subscriptions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.entries.is_empty() {
+            return writeln!(f, "not following any communities");
+        }
+
+        let name_width = column_width("COMMUNITY", self.entries.iter().map(|e| e.name.as_str()));
+        let instance_width =
+            column_width("INSTANCE", self.entries.iter().map(|e| e.instance.as_str()));
+        let state_width = column_width("STATE", self.entries.iter().map(|e| e.state));
+
+        writeln!(
+            f,
+            "{:name_width$}  {:instance_width$}  NSFW  {:state_width$}  TITLE",
+            "COMMUNITY", "INSTANCE", "STATE",
+        )?;
+
+        for entry in &self.entries {
+            let nsfw = if entry.nsfw { "yes" } else { "no" };
+            writeln!(
+                f,
+                "{:name_width$}  {:instance_width$}  {nsfw:4}  {:state_width$}  {}",
+                entry.name, entry.instance, entry.state, entry.title,
+            )?;
+        }
+
+        let pending = self.entries.iter().filter(|e| e.state == "pending").count();
+        writeln!(
+            f,
+            "\n{} communities followed, {pending} pending",
+            self.entries.len()
+        )
+    }
+}
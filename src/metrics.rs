@@ -0,0 +1,201 @@
+use crate::api::{FetchError, ResolveError, SortType};
+use chrono::Utc;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::{sync::LazyLock, time::Duration};
+
+static DISCOVERED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_communities_discovered_total",
+        "Communities found on peers",
+        &["peer", "source", "sort"]
+    )
+    .expect("metric must be valid")
+});
+
+static FOLLOWED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_communities_followed_total",
+        "Communities followed on the local instance",
+        &["peer", "source", "sort"]
+    )
+    .expect("metric must be valid")
+});
+
+static SKIPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_communities_skipped_total",
+        "Communities that were not followed",
+        &["peer", "source", "sort", "reason"]
+    )
+    .expect("metric must be valid")
+});
+
+static FETCH_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_fetch_errors_total",
+        "Errors that occurred while populating from a peer",
+        &["peer", "source", "sort", "error"]
+    )
+    .expect("metric must be valid")
+});
+
+static LAST_SUCCESS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "moco_last_successful_run_timestamp_seconds",
+        "When the populater last completed without errors",
+        &["peer", "source", "sort"]
+    )
+    .expect("metric must be valid")
+});
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_api_requests_total",
+        "Requests made to Lemmy instances",
+        &["instance", "endpoint", "status"]
+    )
+    .expect("metric must be valid")
+});
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "moco_api_request_duration_seconds",
+        "How long requests to Lemmy instances took",
+        &["instance", "endpoint"]
+    )
+    .expect("metric must be valid")
+});
+
+static SERVER_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_api_errors_total",
+        "Errors returned by Lemmy instances",
+        &["instance", "endpoint", "error"]
+    )
+    .expect("metric must be valid")
+});
+
+static PENDING_FOLLOWS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "moco_pending_follows_total",
+        "Follows that remained pending past the timeout",
+        &["instance", "outcome"]
+    )
+    .expect("metric must be valid")
+});
+
+/// Records the metrics for a populater task
+pub struct Task {
+    peer: String,
+    source: &'static str,
+    sort: String,
+}
+
+impl Task {
+    /// Create the metrics for the populater using the source and sort method on the peer
+    pub fn new(peer: &str, source: &'static str, sort: SortType) -> Task {
+        Task {
+            peer: peer.to_owned(),
+            source,
+            sort: format!("{sort:?}"),
+        }
+    }
+
+    /// Record that communities were found on the peer
+    pub fn discovered(&self, count: usize) {
+        DISCOVERED
+            .with_label_values(&[&self.peer, self.source, &self.sort])
+            .inc_by(count as u64);
+    }
+
+    /// Record that a community was followed
+    pub fn followed(&self) {
+        FOLLOWED
+            .with_label_values(&[&self.peer, self.source, &self.sort])
+            .inc();
+    }
+
+    /// Record that a community was skipped
+    pub fn skipped(&self, reason: &str) {
+        SKIPPED
+            .with_label_values(&[&self.peer, self.source, &self.sort, reason])
+            .inc();
+    }
+
+    /// Record that an error occurred
+    pub fn failed(&self, error: &FetchError) {
+        FETCH_ERRORS
+            .with_label_values(&[&self.peer, self.source, &self.sort, error_code(error)])
+            .inc();
+    }
+
+    /// Record that a community could not be resolved or followed
+    pub fn follow_failed(&self, error: &ResolveError) {
+        let code = match error {
+            ResolveError::Mismatch { .. } => "mismatch",
+            ResolveError::Fetch(error) => error_code(error),
+        };
+
+        FETCH_ERRORS
+            .with_label_values(&[&self.peer, self.source, &self.sort, code])
+            .inc();
+    }
+
+    /// Record that the run completed without errors
+    pub fn succeeded(&self) {
+        LAST_SUCCESS
+            .with_label_values(&[&self.peer, self.source, &self.sort])
+            .set(Utc::now().timestamp());
+    }
+}
+
+/// Record a completed request to an instance
+///
+/// The status is `error` if no response was received.
+pub fn request(instance: &str, endpoint: &str, status: Option<u16>, elapsed: Duration) {
+    let status = status.map_or_else(|| "error".to_owned(), |s| s.to_string());
+    REQUESTS
+        .with_label_values(&[instance, endpoint, &status])
+        .inc();
+    REQUEST_DURATION
+        .with_label_values(&[instance, endpoint])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Record an error returned by an instance
+pub fn server_error(instance: &str, endpoint: &str, error: &str) {
+    SERVER_ERRORS
+        .with_label_values(&[instance, endpoint, error])
+        .inc();
+}
+
+/// Record that a follow remained pending, either being retried or abandoned
+pub fn pending_follow(instance: &str, outcome: &str) {
+    PENDING_FOLLOWS
+        .with_label_values(&[instance, outcome])
+        .inc();
+}
+
+/// A short, stable name for the error
+fn error_code(error: &FetchError) -> &str {
+    match error {
+        FetchError::UnsupportedSort => "unsupported_sort",
+        FetchError::ServerError(error) => &error.error,
+        FetchError::Request(_) => "request",
+        FetchError::Deserialize(_) => "deserialize",
+    }
+}
+
+/// Encode the metrics in the Prometheus text format, returning the content type and body
+pub fn encode() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder
+        .encode(&prometheus::gather(), &mut body)
+        .expect("metrics must be encodable");
+
+    (encoder.format_type().to_owned(), body)
+}
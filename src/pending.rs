@@ -0,0 +1,208 @@
+use crate::{
+    api::{CommunityView, FetchError, LemmyApi, SubscribedType},
+    metrics,
+    populater::{periodically, sleep_with_jitter},
+    state::{Event, Refollowed, State, Unfollowed},
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
+
+/// The reason recorded for communities whose follows were never accepted
+const ABANDONED_REASON: &str = "follow not accepted";
+/// The reason recorded for communities that could not be followed again after unfollowing them
+const REFOLLOW_FAILED_REASON: &str = "refollow failed";
+
+/// Shared context passed through to the pending follow checker
+#[derive(Clone)]
+pub struct Context {
+    local: LemmyApi,
+    timeout: Duration,
+    max_retries: u32,
+    add_delay: Duration,
+    dry_run: bool,
+    state: State,
+    /// When follows that moco has no record of were first seen pending
+    first_seen: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+/// Create a new context for the pending follow checker
+pub fn context(
+    local: LemmyApi,
+    timeout: Duration,
+    max_retries: u32,
+    add_delay: Duration,
+    dry_run: bool,
+    state: State,
+) -> Context {
+    Context {
+        local,
+        timeout,
+        max_retries,
+        add_delay,
+        dry_run,
+        state,
+        first_seen: Arc::default(),
+    }
+}
+
+/// Periodically retry follows that have been pending for too long
+pub async fn launch(context: Context, stop: broadcast::Receiver<()>) {
+    info!(dry_run = context.dry_run, "pending follow checker started");
+
+    periodically(context.timeout, stop, || run(&context)).await;
+
+    info!("pending follow checker halted");
+}
+
+/// Check the pending follows once
+pub async fn run(context: &Context) {
+    async {
+        if let Err(error) = resolve(context).await {
+            error!(error = &error as &(dyn std::error::Error + 'static));
+        }
+
+        info!("complete");
+    }
+    .instrument(info_span!("pending"))
+    .await
+}
+
+/// Walk the pending follows, retrying or abandoning any that have timed out
+///
+/// Instances with follows that timed out are reported so they can be investigated.
+#[instrument(name = "resolve", skip_all)]
+async fn resolve(context: &Context) -> Result<(), FetchError> {
+    let pending = context
+        .local
+        .list_subscribed()
+        .await?
+        .into_iter()
+        .filter(|view| view.subscribed == SubscribedType::Pending)
+        .collect::<Vec<_>>();
+    debug!(found = pending.len());
+
+    context
+        .first_seen
+        .lock()
+        .expect("first seen lock poisoned")
+        .retain(|name, _| pending.iter().any(|view| &qualified_name(view) == name));
+
+    let mut failing = BTreeSet::new();
+    for view in pending {
+        match check(&view, context).await {
+            Ok(true) => {
+                failing.insert(instance(&view).to_owned());
+            }
+            Ok(false) => {}
+            Err(error) => {
+                error!(id = view.community.id, actor_id = %view.community.actor_id, error = &error as &(dyn std::error::Error + 'static));
+            }
+        }
+    }
+
+    if !failing.is_empty() {
+        warn!(instances = ?failing, "instances are not accepting follows");
+    }
+
+    Ok(())
+}
+
+/// Retry the follow if it has been pending for too long, giving up once it runs out of retries
+///
+/// Returns whether the follow timed out.
+#[instrument(name = "check", skip_all, fields(name))]
+async fn check(view: &CommunityView, context: &Context) -> Result<bool, FetchError> {
+    let community = &view.community;
+    let instance = instance(view);
+    let name = qualified_name(view);
+    Span::current().record("name", &name);
+
+    let (attempts, since) = match context.state.refollowed(&name) {
+        Some((attempts, at)) => (attempts, at),
+        None => {
+            let since = context.state.followed_at(&name).unwrap_or_else(|| {
+                let mut first_seen = context.first_seen.lock().expect("first seen lock poisoned");
+                *first_seen.entry(name.clone()).or_insert_with(Utc::now)
+            });
+            (0, since)
+        }
+    };
+
+    let timeout = chrono::Duration::from_std(context.timeout).unwrap_or_default();
+    if Utc::now() - since < timeout {
+        debug!(%since, "waiting for follow to be accepted");
+        return Ok(false);
+    }
+
+    if attempts >= context.max_retries {
+        if context.dry_run {
+            warn!(attempts, dry_run = true, "would give up on pending follow");
+            return Ok(true);
+        }
+
+        warn!(attempts, "follow was never accepted, giving up");
+        context.local.unfollow_community(community.id).await?;
+        metrics::pending_follow(instance, "abandoned");
+
+        context.state.record_or_warn(Event::Unfollowed(Unfollowed {
+            name,
+            actor_id: community.actor_id.clone(),
+            reason: ABANDONED_REASON.to_owned(),
+            at: Utc::now(),
+        }));
+
+        return Ok(true);
+    }
+
+    let attempt = attempts + 1;
+    if context.dry_run {
+        info!(attempt, dry_run = true, "would retry pending follow");
+        return Ok(true);
+    }
+
+    info!(attempt, "follow is still pending, following again");
+    context.local.unfollow_community(community.id).await?;
+    sleep_with_jitter(context.add_delay, 0.25).await;
+
+    // The community is no longer followed, so the state must not claim otherwise
+    if let Err(error) = context.local.follow_community(community.id).await {
+        metrics::pending_follow(instance, "failed");
+        context.state.record_or_warn(Event::Unfollowed(Unfollowed {
+            name,
+            actor_id: community.actor_id.clone(),
+            reason: REFOLLOW_FAILED_REASON.to_owned(),
+            at: Utc::now(),
+        }));
+
+        return Err(error);
+    }
+    metrics::pending_follow(instance, "retried");
+
+    context.state.record_or_warn(Event::Refollowed(Refollowed {
+        name,
+        actor_id: community.actor_id.clone(),
+        attempt,
+        at: Utc::now(),
+    }));
+
+    Ok(true)
+}
+
+/// The instance the community belongs to
+fn instance(view: &CommunityView) -> &str {
+    view.community
+        .actor_id
+        .host_str()
+        .expect("community must have a host")
+}
+
+/// The community's name in the form `name@instance`
+fn qualified_name(view: &CommunityView) -> String {
+    format!("{}@{}", view.community.name, instance(view))
+}
@@ -1,68 +1,226 @@
-use crate::api::{Community, FetchError, LemmyApi, ListingType, SortType, SubscribedType};
+use crate::{
+    api::{
+        Community, CommunityViewable, FetchError, LemmyApi, ListingType, ResolveError, SortType,
+        SubscribedType,
+    },
+    cli,
+    federation::Federation,
+    filter::CommunityFilter,
+    health::Health,
+    ignored::Ignored,
+    metrics,
+    state::{Event, Followed, Run, State},
+};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use rand::Rng;
-use std::{collections::HashSet, sync::Arc, time::Duration};
-use tokio::{sync::broadcast, time};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc},
+    time,
+};
 use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
+use url::Url;
+
+mod plan;
+mod report;
+
+pub use plan::Plan;
+pub use report::Report;
+
+/// The source recorded for communities that were followed manually
+pub const MANUAL_SOURCE: &str = "manual";
 
 /// Shared context passed through to the populater
 #[derive(Clone)]
 pub struct Context {
-    local: LemmyApi,
+    shared: Shared,
     peer: LemmyApi,
-    ignored: Arc<HashSet<String>>,
-    add_delay: Duration,
+    nsfw: NsfwPolicy,
+}
+
+/// The parts of the context that are the same for every populater
+///
+/// When a plan is provided, communities are recorded in it instead of being followed.
+#[derive(Clone)]
+pub struct Shared {
+    pub local: LemmyApi,
+    pub ignored: Ignored,
+    pub federation: Federation,
+    pub filter: CommunityFilter,
+    pub add_delay: Duration,
+    pub state: State,
+    pub plan: Option<Plan>,
+    pub health: Health,
 }
 
 /// Create a new context for the populaters
-pub fn context(
-    local: LemmyApi,
-    peer: LemmyApi,
-    ignored: Arc<HashSet<String>>,
-    add_delay: Duration,
-) -> Context {
-    Context {
-        local,
-        peer,
-        ignored,
-        add_delay,
-    }
+pub fn context(shared: Shared, peer: LemmyApi, nsfw: NsfwPolicy) -> Context {
+    Context { shared, peer, nsfw }
+}
+
+/// Commands that can be sent to a running populater
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Command {
+    /// Run immediately, even if paused
+    Run,
+    /// Skip scheduled runs until resumed
+    Pause,
+    /// Resume scheduled runs
+    Resume,
+    /// Exit once any in-progress run completes
+    Stop,
+}
+
+/// The observable state of a running populater
+///
+/// Whether the populater is paused is kept up to date by whoever sends it commands.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    pub paused: bool,
+    pub last_run: Option<DateTime<Utc>>,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
 }
 
 /// Periodically populate the local instance from the peer using the specified source
+///
+/// The populater starts paused if the status says so, and exits when it receives
+/// [`Command::Stop`] or every sender is dropped.
 pub async fn launch<S: CommunitySource>(
     context: Context,
     sort: SortType,
     limit: i32,
     interval: Duration,
-    mut stop: broadcast::Receiver<()>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    status: Arc<Mutex<Status>>,
 ) {
     let instance = context.peer.instance();
     let kind = S::kind();
 
     info!(%instance, %kind, ?sort, "populater started");
 
-    sleep_with_jitter(Duration::from_secs(5), 0.5).await;
+    let heartbeat = context
+        .shared
+        .health
+        .register(format!("{instance}/{kind}/{sort:?}"), interval);
+
+    let mut delay = jitter(Duration::from_secs(5), 0.5);
+    let mut paused = status.lock().expect("status lock poisoned").paused;
+    'populater: loop {
+        set_status(&status, |s| s.next_run = Some(Utc::now() + delay));
+
+        let wake = time::sleep(delay);
+        tokio::pin!(wake);
+        let triggered = loop {
+            tokio::select! {
+                _ = &mut wake => break false,
+                command = commands.recv() => match command {
+                    Some(Command::Run) => break true,
+                    Some(Command::Pause) => paused = true,
+                    Some(Command::Resume) => paused = false,
+                    Some(Command::Stop) | None => break 'populater,
+                },
+            }
 
-    loop {
-        async {
-            if let Err(error) =
-                populate::<S>(&context, sort, limit).await
-            {
+            info!(%instance, %kind, ?sort, paused, "populater paused or resumed");
+        };
+
+        if triggered || !paused {
+            let run = run::<S>(&context, sort, limit).await;
+            set_status(&status, |s| {
+                s.last_run = Some(run.finished_at);
+                s.last_error = run.error;
+            });
+        }
+        heartbeat.beat();
+
+        delay = jitter(interval, 0.1);
+    }
+
+    info!(%instance, %kind, ?sort, "populater halted");
+}
+
+/// Update the populater's status
+fn set_status(status: &Mutex<Status>, update: impl FnOnce(&mut Status)) {
+    update(&mut status.lock().expect("status lock poisoned"));
+}
+
+/// Populate the local instance from the peer once, recording the outcome
+pub async fn run<S: CommunitySource>(context: &Context, sort: SortType, limit: i32) -> Run {
+    let instance = context.peer.instance();
+    let kind = S::kind();
+
+    async {
+        let metrics = metrics::Task::new(instance, kind, sort);
+
+        let started_at = Utc::now();
+        let result = populate::<S>(context, sort, limit, &metrics).await;
+
+        let mut run = Run {
+            peer: instance.to_owned(),
+            source: kind.to_owned(),
+            sort,
+            started_at,
+            finished_at: Utc::now(),
+            discovered: 0,
+            followed: 0,
+            skipped: 0,
+            failed: 0,
+            error: None,
+        };
+        match result {
+            Ok(summary) => {
+                if summary.failed == 0 {
+                    metrics.succeeded();
+                }
+
+                run.discovered = summary.discovered;
+                run.followed = summary.followed;
+                run.skipped = summary.skipped;
+                run.failed = summary.failed;
+            }
+            Err(error) => {
+                metrics.failed(&error);
                 error!(%instance, %kind, ?sort, error = &error as &(dyn std::error::Error + 'static));
+                run.error = Some(error.to_string());
             }
-
-            info!(%instance, %kind, ?sort, "complete, waiting until next interval");
         }
-            .instrument(info_span!("populater", %instance, %kind, ?sort))
-            .await;
 
-        tokio::select! {
-            _ = stop.recv() => break,
-            _ = sleep_with_jitter(interval, 0.1) => {},
+        if context.shared.plan.is_none() {
+            context.shared.state.record_or_warn(Event::Run(run.clone()));
         }
+
+        info!(%instance, %kind, ?sort, "complete");
+        run
     }
+    .instrument(info_span!("populater", %instance, %kind, ?sort))
+    .await
+}
 
-    info!(%instance, %kind, ?sort, "populater halted");
+/// The counts of what happened during a population
+#[derive(Debug, Default)]
+struct Summary {
+    discovered: usize,
+    followed: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+/// What happened to a single community
+pub enum Outcome {
+    Followed,
+    /// The community would have been followed, but was recorded in the plan instead
+    Planned,
+    /// The community was not followed for the reason
+    Skipped(&'static str),
 }
 
 /// Perform the population for the peer
@@ -71,33 +229,95 @@ async fn populate<S: CommunitySource>(
     context: &Context,
     sort: SortType,
     limit: i32,
-) -> Result<(), FetchError> {
+    metrics: &metrics::Task,
+) -> Result<Summary, FetchError> {
     let mut processed = HashSet::new();
 
-    let communities = S::fetch(&context.peer, ListingType::Local, sort, limit).await?;
+    let communities =
+        S::fetch(&context.peer, ListingType::Local, sort, context.nsfw, limit).await?;
     debug!(found = communities.len());
+    metrics.discovered(communities.len());
+
+    let mut summary = Summary {
+        discovered: communities.len(),
+        ..Default::default()
+    };
 
     for community in communities {
-        if let Err(error) = check(&community, &mut processed, context).await {
-            error!(id = community.id, actor_id = %community.actor_id, error = &error as &(dyn std::error::Error + 'static));
+        match check(&community, S::kind(), Some(sort), &mut processed, context).await {
+            Ok(Outcome::Followed) => {
+                summary.followed += 1;
+                metrics.followed();
+            }
+            Ok(Outcome::Planned) => summary.followed += 1,
+            Ok(Outcome::Skipped(reason)) => {
+                summary.skipped += 1;
+                metrics.skipped(reason);
+            }
+            Err(error) => {
+                summary.failed += 1;
+                metrics.follow_failed(&error);
+                error!(id = community.id, actor_id = %community.actor_id, error = &error as &(dyn std::error::Error + 'static));
+            }
         }
     }
 
-    Ok(())
+    Ok(summary)
+}
+
+/// Follow a community by its `[!]name@instance`, applying the same checks as the populaters
+///
+/// The community is resolved on its home instance, which the context's peer must be.
+#[instrument(name = "follow", skip(context))]
+pub async fn follow(context: &Context, name: &str) -> Result<Outcome, ResolveError> {
+    let name = match cli::parse_community(name) {
+        Ok(name) => name,
+        Err(_) => return Ok(skip("invalid community name")),
+    };
+
+    let community = match context.peer.resolve_object(&format!("!{name}")).await? {
+        Some(view) => view.community,
+        None => {
+            warn!("community does not exist on instance");
+            return Ok(Outcome::Skipped("community does not exist on instance"));
+        }
+    };
+
+    check(
+        &community,
+        MANUAL_SOURCE,
+        None,
+        &mut HashSet::new(),
+        context,
+    )
+    .await
 }
 
 /// Check the community
+///
+/// The sort method is only known for communities discovered by a populater.
 #[instrument(name = "check", skip_all, fields(name))]
 async fn check(
     community: &Community,
+    source: &'static str,
+    sort: Option<SortType>,
     processed: &mut HashSet<String>,
     Context {
-        local,
+        shared:
+            Shared {
+                local,
+                ignored,
+                federation,
+                filter,
+                add_delay,
+                state,
+                plan,
+                ..
+            },
         peer,
-        ignored,
-        add_delay,
+        nsfw,
     }: &Context,
-) -> Result<(), FetchError> {
+) -> Result<Outcome, ResolveError> {
     let instance = community
         .actor_id
         .host_str()
@@ -106,40 +326,109 @@ async fn check(
     Span::current().record("name", &name);
 
     if ignored.contains(instance) {
-        info!(skipped = true, reason = "in ignore list");
-        return Ok(());
+        return Ok(skip("in ignore list"));
+    }
+    if let Some(reason) = federation.rejects(instance) {
+        return Ok(skip(reason));
+    }
+    if !nsfw.allows(community) {
+        return Ok(skip("excluded by nsfw policy"));
+    }
+    if let Some(rule) = filter.rejects(&name, instance, &community.title) {
+        info!(skipped = true, reason = "filtered", %rule);
+        return Ok(Outcome::Skipped("filtered"));
     }
     if processed.contains(&name) {
-        info!(skipped = true, reason = "already processed community");
-        return Ok(());
+        return Ok(skip("already processed community"));
+    }
+    if state.is_followed(&name) {
+        return Ok(skip("previously followed community"));
     }
+    if state.is_pruned(&name) {
+        return Ok(skip("previously pruned community"));
+    }
+
+    // The community being known locally doesn't mean the bot account follows it
+    if let Some(response) = local.get_community(&name).await? {
+        match response.community_view.subscribed {
+            SubscribedType::Subscribed => return Ok(skip("already subscribed to community")),
+            SubscribedType::Pending => return Ok(skip("subscription pending")),
+            SubscribedType::NotSubscribed => debug!("community is known locally but not followed"),
+        }
+    }
+
+    // Resolving makes the local instance fetch the community, which a dry run shouldn't do
+    if let Some(plan) = plan {
+        info!(dry_run = true, "would follow new community");
+        plan.add(peer.instance(), source, sort, &name, &community.title);
+        processed.insert(name);
 
-    if local.get_community(&name).await?.is_some() {
-        info!(skipped = true, reason = "already subscribed to community");
-        return Ok(());
+        return Ok(Outcome::Planned);
     }
 
-    let community = match peer.resolve_object(community.actor_id.as_str()).await? {
-        Some(c) => c,
+    // The peer's ids mean nothing to the local instance, so the community must be resolved there
+    let view = match local.resolve_community(&community.actor_id).await? {
+        Some(view) => view,
         None => {
             warn!("community does not exist on instance");
-            return Ok(());
+            return Ok(Outcome::Skipped("community does not exist on instance"));
         }
     };
 
     sleep_with_jitter(*add_delay, 0.25).await;
 
-    info!("following new community");
-    local.follow_community(community.community.id).await?;
-
+    info!(id = view.community.id, "following new community");
+    local.follow_community(view.community.id).await?;
+
+    state.record_or_warn(Event::Followed(Followed {
+        name: name.clone(),
+        actor_id: view.community.actor_id,
+        peer: peer.instance().to_owned(),
+        source: source.to_owned(),
+        sort,
+        at: Utc::now(),
+    }));
     processed.insert(name);
 
-    Ok(())
+    Ok(Outcome::Followed)
+}
+
+/// Log that the community is being skipped
+fn skip(reason: &'static str) -> Outcome {
+    info!(skipped = true, reason);
+    Outcome::Skipped(reason)
+}
+
+/// Run the task every interval, with jitter, until told to stop
+///
+/// The first run happens after one interval, and a stop signal only interrupts the wait, never a
+/// run in progress.
+pub async fn periodically<F, Fut>(
+    interval: Duration,
+    mut stop: broadcast::Receiver<()>,
+    mut task: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    loop {
+        tokio::select! {
+            _ = stop.recv() => break,
+            _ = sleep_with_jitter(interval, 0.1) => {},
+        }
+
+        task().await;
+    }
 }
 
-/// Sleep the specified amount +/- a 5% jitter
+/// Sleep the specified amount +/- a percentage of jitter
 #[instrument(level = "debug", fields(duration = duration.as_secs()))]
-async fn sleep_with_jitter(duration: Duration, max_percent: f64) {
+pub async fn sleep_with_jitter(duration: Duration, max_percent: f64) {
+    time::sleep(jitter(duration, max_percent)).await
+}
+
+/// Add or subtract a random percentage of the duration
+fn jitter(duration: Duration, max_percent: f64) -> Duration {
     let secs = duration.as_secs_f64();
 
     let jitter = {
@@ -151,7 +440,103 @@ async fn sleep_with_jitter(duration: Duration, max_percent: f64) {
     };
 
     debug!(jitter, total = secs + jitter);
-    time::sleep(Duration::from_secs_f64(secs + jitter)).await
+    Duration::from_secs_f64(secs + jitter)
+}
+
+/// The sources communities can be discovered from
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    /// The peer's community listing
+    Communities,
+    /// The communities of the peer's posts
+    Posts,
+    /// The communities in the peer's `All` listing with the most posts and comments recently
+    ///
+    /// The newest posts within the last day are always used, so only one trending populater runs
+    /// per peer regardless of the sort methods and post count.
+    Trending,
+    /// The communities of the peer's comments
+    Comments,
+}
+
+/// Which communities to consider based on whether they are NSFW
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NsfwPolicy {
+    /// Only consider communities that are not NSFW
+    #[default]
+    Exclude,
+    /// Consider every community
+    Include,
+    /// Only consider NSFW communities
+    Only,
+}
+
+impl NsfwPolicy {
+    /// Whether NSFW communities must be requested from the peer
+    fn show_nsfw(self) -> bool {
+        self != Self::Exclude
+    }
+
+    /// Whether the policy allows the community
+    fn allows(self, community: &Community) -> bool {
+        match self {
+            Self::Exclude => !community.nsfw,
+            Self::Include => true,
+            Self::Only => community.nsfw,
+        }
+    }
+}
+
+impl Source {
+    /// The name of the source used in metrics, logs, and the state journal
+    pub fn kind(self) -> &'static str {
+        match self {
+            Self::Communities => FromCommunities::kind(),
+            Self::Posts => FromPosts::kind(),
+            Self::Trending => FromTrending::kind(),
+            Self::Comments => FromComments::kind(),
+        }
+    }
+
+    /// Periodically populate the local instance using this source
+    pub async fn launch(
+        self,
+        context: Context,
+        sort: SortType,
+        limit: i32,
+        interval: Duration,
+        commands: mpsc::UnboundedReceiver<Command>,
+        status: Arc<Mutex<Status>>,
+    ) {
+        match self {
+            Self::Communities => {
+                launch::<FromCommunities>(context, sort, limit, interval, commands, status).await
+            }
+            Self::Posts => {
+                launch::<FromPosts>(context, sort, limit, interval, commands, status).await
+            }
+            Self::Trending => {
+                launch::<FromTrending>(context, sort, limit, interval, commands, status).await
+            }
+            Self::Comments => {
+                launch::<FromComments>(context, sort, limit, interval, commands, status).await
+            }
+        }
+    }
+
+    /// Populate the local instance once using this source
+    pub async fn run(self, context: &Context, sort: SortType, limit: i32) -> Run {
+        match self {
+            Self::Communities => run::<FromCommunities>(context, sort, limit).await,
+            Self::Posts => run::<FromPosts>(context, sort, limit).await,
+            Self::Trending => run::<FromTrending>(context, sort, limit).await,
+            Self::Comments => run::<FromComments>(context, sort, limit).await,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -162,10 +547,18 @@ pub trait CommunitySource {
         api: &LemmyApi,
         type_: ListingType,
         sort: SortType,
+        nsfw: NsfwPolicy,
         limit: i32,
     ) -> Result<Vec<Community>, FetchError>;
 }
 
+/// Whether a community found by a source should be considered for following
+fn candidate<V: CommunityViewable>(view: &V, nsfw: NsfwPolicy) -> bool {
+    !view.blocked()
+        && view.subscribed() == SubscribedType::NotSubscribed
+        && nsfw.allows(view.community())
+}
+
 /// Populate from communities
 pub struct FromCommunities;
 
@@ -179,13 +572,16 @@ impl CommunitySource for FromCommunities {
         api: &LemmyApi,
         type_: ListingType,
         sort: SortType,
+        nsfw: NsfwPolicy,
         limit: i32,
     ) -> Result<Vec<Community>, FetchError> {
-        let views = api.list_communities(type_, sort, false, limit).await?;
+        let views = api
+            .list_communities(type_, sort, nsfw.show_nsfw(), limit)
+            .await?;
 
         let communities = views
             .into_iter()
-            .filter(|c| !c.blocked && c.subscribed == SubscribedType::NotSubscribed)
+            .filter(|c| candidate(c, nsfw))
             .map(|c| c.community)
             .collect();
         Ok(communities)
@@ -205,15 +601,133 @@ impl CommunitySource for FromPosts {
         api: &LemmyApi,
         type_: ListingType,
         sort: SortType,
+        nsfw: NsfwPolicy,
         limit: i32,
     ) -> Result<Vec<Community>, FetchError> {
         let views = api.get_posts(type_, sort, None, limit).await?;
 
         let communities = views
             .into_iter()
-            .filter(|p| !p.creator_blocked && p.subscribed == SubscribedType::NotSubscribed)
+            .filter(|p| candidate(p, nsfw))
             .map(|p| p.community)
             .collect();
         Ok(communities)
     }
 }
+
+/// Populate from comments
+///
+/// Comments can't be sorted by time period, so the sort method is mapped to the closest one.
+pub struct FromComments;
+
+#[async_trait::async_trait]
+impl CommunitySource for FromComments {
+    fn kind() -> &'static str {
+        "comments"
+    }
+
+    async fn fetch(
+        api: &LemmyApi,
+        type_: ListingType,
+        sort: SortType,
+        nsfw: NsfwPolicy,
+        limit: i32,
+    ) -> Result<Vec<Community>, FetchError> {
+        let views = api.get_comments(type_, sort.into(), limit).await?;
+
+        let communities = views
+            .into_iter()
+            .filter(|c| candidate(c, nsfw))
+            .map(|c| c.community)
+            .collect();
+        Ok(communities)
+    }
+}
+
+/// Populate from the communities that are the most active right now
+///
+/// The peer's `All` listing is paged back through until it covers the trending window, and the
+/// posts are grouped by community. Each community is ranked by how many posts and comments it gets
+/// per hour, measured from its oldest post within the window.
+pub struct FromTrending;
+
+#[async_trait::async_trait]
+impl CommunitySource for FromTrending {
+    fn kind() -> &'static str {
+        "trending"
+    }
+
+    async fn fetch(
+        api: &LemmyApi,
+        _type: ListingType,
+        _sort: SortType,
+        nsfw: NsfwPolicy,
+        _limit: i32,
+    ) -> Result<Vec<Community>, FetchError> {
+        let now = Utc::now();
+        let views = api
+            .get_posts_since(ListingType::All, now - TRENDING_WINDOW, TRENDING_MAX_POSTS)
+            .await?;
+
+        let mut activity = HashMap::<Url, Activity>::new();
+        for view in views.into_iter().filter(|p| candidate(p, nsfw)) {
+            let entry = activity
+                .entry(view.community.actor_id.clone())
+                .or_insert_with(|| Activity {
+                    community: view.community,
+                    posts: 0,
+                    comments: 0,
+                    oldest: view.post.published,
+                });
+            entry.posts += 1;
+            entry.comments += view.counts.comments;
+            entry.oldest = entry.oldest.min(view.post.published);
+        }
+
+        let mut ranked = activity
+            .into_values()
+            .map(|a| (a.velocity(now), a))
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+        let communities = ranked
+            .into_iter()
+            .map(|(velocity, a)| {
+                debug!(actor_id = %a.community.actor_id, posts = a.posts, comments = a.comments, velocity);
+                a.community
+            })
+            .collect();
+        Ok(communities)
+    }
+}
+
+/// How posts are sorted when looking for trending communities
+pub const TRENDING_SORT: SortType = SortType::New;
+
+/// How recently posts must have been published to count towards a community trending
+const TRENDING_WINDOW: chrono::Duration = chrono::Duration::hours(24);
+
+/// The most posts to page through when looking for trending communities on busy peers
+const TRENDING_MAX_POSTS: i32 = 2000;
+
+/// How much a comment counts towards a community trending compared to a post
+const COMMENT_WEIGHT: f64 = 0.1;
+
+/// The recent activity in a community
+struct Activity {
+    community: Community,
+    posts: u32,
+    comments: i64,
+    /// When the community's oldest post within the trending window was published
+    oldest: DateTime<Utc>,
+}
+
+impl Activity {
+    /// The weighted number of posts and comments per hour since the oldest post
+    ///
+    /// Spans shorter than an hour count as an hour so a single new post doesn't dominate.
+    fn velocity(&self, now: DateTime<Utc>) -> f64 {
+        let hours = ((now - self.oldest).num_seconds() as f64 / 3600.0).max(1.0);
+        (self.posts as f64 + self.comments as f64 * COMMENT_WEIGHT) / hours
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{api::SortType, table::column_width};
+use std::{
+    collections::HashSet,
+    fmt::{self, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// The communities that would be followed, collected during a dry run
+#[derive(Clone, Default)]
+pub struct Plan {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+struct Entry {
+    peer: String,
+    source: &'static str,
+    sort: String,
+    name: String,
+    title: String,
+}
+
+impl Plan {
+    /// Record a community that would be followed
+    pub fn add(
+        &self,
+        peer: &str,
+        source: &'static str,
+        sort: Option<SortType>,
+        name: &str,
+        title: &str,
+    ) {
+        let mut entries = self.entries.lock().expect("plan lock poisoned");
+        entries.push(Entry {
+            peer: peer.to_owned(),
+            source,
+            sort: sort.map_or_else(|| "-".to_owned(), |sort| format!("{sort:?}")),
+            name: name.to_owned(),
+            title: title.to_owned(),
+        });
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut entries = self.entries.lock().expect("plan lock poisoned");
+        if entries.is_empty() {
+            return writeln!(f, "no communities would be followed");
+        }
+
+        entries.sort_by(|a, b| {
+            (&a.peer, a.source, &a.sort, &a.name).cmp(&(&b.peer, b.source, &b.sort, &b.name))
+        });
+
+        let peer_width = column_width("PEER", entries.iter().map(|e| e.peer.as_str()));
+        let source_width = column_width("SOURCE", entries.iter().map(|e| e.source));
+        let sort_width = column_width("SORT", entries.iter().map(|e| e.sort.as_str()));
+        let name_width = column_width("COMMUNITY", entries.iter().map(|e| e.name.as_str()));
+
+        writeln!(
+            f,
+            "{:peer_width$}  {:source_width$}  {:sort_width$}  {:name_width$}  TITLE",
+            "PEER", "SOURCE", "SORT", "COMMUNITY",
+        )?;
+
+        let mut previous = None;
+        for entry in entries.iter() {
+            // Only print the group columns when the group changes
+            let group = (&entry.peer, entry.source, &entry.sort);
+            let (peer, source, sort) = if previous == Some(group) {
+                ("", "", "")
+            } else {
+                (entry.peer.as_str(), entry.source, entry.sort.as_str())
+            };
+            previous = Some(group);
+
+            writeln!(
+                f,
+                "{peer:peer_width$}  {source:source_width$}  {sort:sort_width$}  {:name_width$}  {}",
+                entry.name, entry.title,
+            )?;
+        }
+
+        // The same community can be discovered by several populaters, but is only followed once
+        let communities = entries
+            .iter()
+            .map(|e| e.name.as_str())
+            .collect::<HashSet<_>>();
+        writeln!(f, "\n{} communities would be followed", communities.len())
+    }
+}
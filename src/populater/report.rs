@@ -0,0 +1,70 @@
+use crate::{state::Run, table::column_width};
+use std::fmt::{self, Formatter};
+
+/// A summary of the runs made when every populater runs once
+pub struct Report {
+    runs: Vec<Run>,
+}
+
+impl Report {
+    /// Summarize the runs
+    pub fn new(mut runs: Vec<Run>) -> Report {
+        runs.sort_by_cached_key(|r| (r.peer.clone(), r.source.clone(), format!("{:?}", r.sort)));
+        Report { runs }
+    }
+
+    /// The number of runs that failed or could not check some communities
+    pub fn failures(&self) -> usize {
+        self.runs.iter().filter(|run| failed(run)).count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.runs.is_empty() {
+            return writeln!(f, "no populaters were run");
+        }
+
+        let sorts = self
+            .runs
+            .iter()
+            .map(|run| format!("{:?}", run.sort))
+            .collect::<Vec<_>>();
+
+        let peer_width = column_width("PEER", self.runs.iter().map(|r| r.peer.as_str()));
+        let source_width = column_width("SOURCE", self.runs.iter().map(|r| r.source.as_str()));
+        let sort_width = column_width("SORT", sorts.iter().map(String::as_str));
+
+        writeln!(
+            f,
+            "{:peer_width$}  {:source_width$}  {:sort_width$}  DISCOVERED  FOLLOWED  SKIPPED  FAILED  ERROR",
+            "PEER", "SOURCE", "SORT",
+        )?;
+
+        for (run, sort) in self.runs.iter().zip(&sorts) {
+            writeln!(
+                f,
+                "{:peer_width$}  {:source_width$}  {sort:sort_width$}  {:>10}  {:>8}  {:>7}  {:>6}  {}",
+                run.peer,
+                run.source,
+                run.discovered,
+                run.followed,
+                run.skipped,
+                run.failed,
+                run.error.as_deref().unwrap_or("-"),
+            )?;
+        }
+
+        let failures = self.failures();
+        if failures == 0 {
+            writeln!(f, "\nall {} populations succeeded", self.runs.len())
+        } else {
+            writeln!(f, "\n{failures} of {} populations failed", self.runs.len())
+        }
+    }
+}
+
+/// Whether the run failed or could not check some communities
+fn failed(run: &Run) -> bool {
+    run.error.is_some() || run.failed > 0
+}
@@ -0,0 +1,171 @@
+use crate::{
+    api::{CommunityView, FetchError, LemmyApi, ListingType, SortType},
+    federation::Federation,
+    ignored::Ignored,
+    populater::{periodically, sleep_with_jitter},
+    state::{Event, State, Unfollowed},
+};
+use chrono::Utc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, info_span, instrument, Instrument, Span};
+
+/// How many of a community's newest posts to fetch when checking whether it is active
+const LATEST_POSTS: i32 = 10;
+
+/// Shared context passed through to the pruner
+#[derive(Clone)]
+pub struct Context {
+    local: LemmyApi,
+    ignored: Ignored,
+    federation: Federation,
+    inactive_after: Duration,
+    remove_delay: Duration,
+    dry_run: bool,
+    state: State,
+}
+
+/// Create a new context for the pruner
+pub fn context(
+    local: LemmyApi,
+    ignored: Ignored,
+    federation: Federation,
+    inactive_after: Duration,
+    remove_delay: Duration,
+    dry_run: bool,
+    state: State,
+) -> Context {
+    Context {
+        local,
+        ignored,
+        federation,
+        inactive_after,
+        remove_delay,
+        dry_run,
+        state,
+    }
+}
+
+/// Periodically unfollow stale communities from the local instance
+pub async fn launch(context: Context, interval: Duration, stop: broadcast::Receiver<()>) {
+    info!(dry_run = context.dry_run, "pruner started");
+
+    periodically(interval, stop, || run(&context)).await;
+
+    info!("pruner halted");
+}
+
+/// Prune the subscribed communities once
+pub async fn run(context: &Context) {
+    async {
+        if let Err(error) = prune(context).await {
+            error!(error = &error as &(dyn std::error::Error + 'static));
+        }
+
+        info!("complete");
+    }
+    .instrument(info_span!("pruner"))
+    .await
+}
+
+/// Walk the subscribed communities and unfollow any that are stale
+#[instrument(name = "prune", skip_all)]
+async fn prune(context: &Context) -> Result<(), FetchError> {
+    let subscriptions = context.local.list_subscribed().await?;
+    debug!(found = subscriptions.len());
+
+    for view in subscriptions {
+        if let Err(error) = check(&view, context).await {
+            error!(id = view.community.id, actor_id = %view.community.actor_id, error = &error as &(dyn std::error::Error + 'static));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether the community is stale, unfollowing it if it is
+#[instrument(name = "check", skip_all, fields(name))]
+async fn check(
+    view: &CommunityView,
+    Context {
+        local,
+        ignored,
+        federation,
+        inactive_after,
+        remove_delay,
+        dry_run,
+        state,
+    }: &Context,
+) -> Result<(), FetchError> {
+    let community = &view.community;
+    let instance = community
+        .actor_id
+        .host_str()
+        .expect("community must have a host");
+    let name = format!("{}@{instance}", community.name);
+    Span::current().record("name", &name);
+
+    let reason = if community.removed {
+        "removed"
+    } else if community.hidden {
+        "hidden"
+    } else if ignored.contains(instance) {
+        "in ignore list"
+    } else if let Some(reason) = federation.rejects(instance) {
+        reason
+    } else {
+        let cutoff = Utc::now() - chrono::Duration::from_std(*inactive_after).unwrap_or_default();
+
+        // Give newly followed communities time for posts to federate
+        if let Some(followed_at) = state.followed_at(&name) {
+            if followed_at >= cutoff {
+                debug!(%followed_at, "community was recently followed");
+                return Ok(());
+            }
+        }
+
+        // Pinned posts always come first, so look past them for the newest post
+        let posts = local
+            .get_posts(
+                ListingType::All,
+                SortType::New,
+                Some(community.id),
+                LATEST_POSTS,
+            )
+            .await?;
+        let latest = posts
+            .iter()
+            .filter(|p| !p.post.featured_community)
+            .map(|p| p.post.published)
+            .max()
+            .or_else(|| posts.iter().map(|p| p.post.published).max());
+
+        match latest {
+            Some(last_post) if last_post >= cutoff => {
+                debug!(%last_post, "community is active");
+                return Ok(());
+            }
+            Some(_) => "inactive",
+            None => "no posts",
+        }
+    };
+
+    if *dry_run {
+        info!(reason, dry_run = true, "would unfollow community");
+        return Ok(());
+    }
+
+    sleep_with_jitter(*remove_delay, 0.25).await;
+
+    info!(reason, "unfollowing community");
+    local.unfollow_community(community.id).await?;
+
+    state.record_or_warn(Event::Unfollowed(Unfollowed {
+        name,
+        actor_id: community.actor_id.clone(),
+        reason: reason.to_owned(),
+        at: Utc::now(),
+    }));
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::{health::Health, metrics};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+/// Bind to the address, returning a future that serves metrics and health checks until stopped
+///
+/// Exposes the Prometheus metrics at `/metrics`, liveness at `/healthz`, and readiness at
+/// `/readyz`.
+pub fn observability(
+    address: SocketAddr,
+    health: Health,
+    stop: broadcast::Receiver<()>,
+) -> Result<impl Future<Output = ()>, hyper::Error> {
+    serve("metrics and health checks", address, stop, move |request| {
+        let response = observe(request, &health);
+        async move { response }
+    })
+}
+
+/// Bind to the address, returning a future that handles requests until stopped
+pub fn serve<H, F>(
+    name: &'static str,
+    address: SocketAddr,
+    mut stop: broadcast::Receiver<()>,
+    handler: H,
+) -> Result<impl Future<Output = ()>, hyper::Error>
+where
+    H: Fn(Request<Body>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handler(request);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            let _ = stop.recv().await;
+        });
+
+    Ok(async move {
+        info!(%address, "serving {name}");
+
+        if let Err(error) = server.await {
+            error!(
+                error = &error as &(dyn std::error::Error + 'static),
+                "failed to serve {name}"
+            );
+        }
+    })
+}
+
+/// Route requests for metrics and health checks
+fn observe(request: Request<Body>, health: &Health) -> Response<Body> {
+    if request.method() != Method::GET {
+        return text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+
+    match request.uri().path() {
+        "/metrics" => {
+            let (content_type, body) = metrics::encode();
+            Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .expect("response must be valid")
+        }
+        "/healthz" => {
+            let stalled = health.stalled();
+            if stalled.is_empty() {
+                text(StatusCode::OK, "ok")
+            } else {
+                let message = format!("stalled populaters: {}", stalled.join(", "));
+                text(StatusCode::SERVICE_UNAVAILABLE, &message)
+            }
+        }
+        "/readyz" => {
+            if health.is_ready() {
+                text(StatusCode::OK, "ok")
+            } else {
+                text(StatusCode::SERVICE_UNAVAILABLE, "not logged in")
+            }
+        }
+        _ => text(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+/// Build a plain text response
+pub fn text(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(format!("{message}\n")))
+        .expect("response must be valid")
+}
+
+/// Build a JSON response
+pub fn json<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let body = serde_json::to_vec(body).expect("response must be serializable");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("response must be valid")
+}
@@ -0,0 +1,278 @@
+use crate::api::SortType;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+/// A persistent, append-only journal of the actions moco has taken
+///
+/// Every event is written as a single line of JSON so the file can be audited with standard
+/// tooling. The journal is replayed on startup to restore the set of followed and pruned
+/// communities. Without a file, events are only kept in memory for the lifetime of the process.
+#[derive(Clone)]
+pub struct State {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    file: Option<File>,
+    followed: HashMap<String, Followed>,
+    pruned: HashSet<String>,
+    refollowed: HashMap<String, Refollowed>,
+}
+
+impl Inner {
+    fn new(file: Option<File>) -> Inner {
+        Inner {
+            file,
+            followed: HashMap::new(),
+            pruned: HashSet::new(),
+            refollowed: HashMap::new(),
+        }
+    }
+
+    /// Update the in-memory view with the event
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Followed(f) => {
+                self.pruned.remove(&f.name);
+                self.refollowed.remove(&f.name);
+                self.followed.insert(f.name.clone(), f);
+            }
+            Event::Unfollowed(u) => {
+                self.followed.remove(&u.name);
+                self.refollowed.remove(&u.name);
+                self.pruned.insert(u.name);
+            }
+            Event::Refollowed(r) => {
+                self.refollowed.insert(r.name.clone(), r);
+            }
+            Event::Run(_) => {}
+        }
+    }
+}
+
+impl State {
+    /// Open the journal at the given path, creating it if it does not exist
+    #[instrument(name = "State::open", fields(path = %path.display()))]
+    pub fn open(path: &Path) -> Result<State, StateError> {
+        let mut events = Vec::new();
+
+        match File::open(path) {
+            Ok(file) => {
+                for (i, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let event = serde_json::from_str::<Event>(&line)
+                        .map_err(|e| StateError::Corrupt(i + 1, e))?;
+                    events.push(event);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        let mut inner = Inner::new(Some(file));
+        for event in events {
+            inner.apply(event);
+        }
+
+        debug!(
+            followed = inner.followed.len(),
+            pruned = inner.pruned.len(),
+            "loaded state"
+        );
+
+        Ok(State {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Create an empty state that is only kept in memory
+    pub fn in_memory() -> State {
+        State {
+            inner: Arc::new(Mutex::new(Inner::new(None))),
+        }
+    }
+
+    /// Check whether moco has previously followed the community
+    pub fn is_followed(&self, name: &str) -> bool {
+        let inner = self.inner.lock().expect("state lock poisoned");
+        inner.followed.contains_key(name)
+    }
+
+    /// Get when moco followed the community, if it did
+    pub fn followed_at(&self, name: &str) -> Option<DateTime<Utc>> {
+        let inner = self.inner.lock().expect("state lock poisoned");
+        inner.followed.get(name).map(|f| f.at)
+    }
+
+    /// Check whether moco has previously pruned the community
+    pub fn is_pruned(&self, name: &str) -> bool {
+        let inner = self.inner.lock().expect("state lock poisoned");
+        inner.pruned.contains(name)
+    }
+
+    /// Get how many times a pending follow has been retried, and when it was last retried
+    pub fn refollowed(&self, name: &str) -> Option<(u32, DateTime<Utc>)> {
+        let inner = self.inner.lock().expect("state lock poisoned");
+        inner.refollowed.get(name).map(|r| (r.attempt, r.at))
+    }
+
+    /// Append an event to the journal
+    pub fn record(&self, event: Event) -> Result<(), StateError> {
+        let mut inner = self.inner.lock().expect("state lock poisoned");
+        if let Some(file) = &mut inner.file {
+            let mut line = serde_json::to_string(&event).map_err(StateError::Serialize)?;
+            line.push('\n');
+
+            file.write_all(line.as_bytes())?;
+            file.flush()?;
+        }
+        inner.apply(event);
+
+        Ok(())
+    }
+
+    /// Append an event to the journal, logging any failures
+    pub fn record_or_warn(&self, event: Event) {
+        if let Err(error) = self.record(event) {
+            warn!(
+                error = &error as &(dyn std::error::Error + 'static),
+                "failed to record event"
+            );
+        }
+    }
+}
+
+/// An entry in the journal
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A community was followed
+    Followed(Followed),
+    /// A community was unfollowed by the pruner or manually
+    Unfollowed(Unfollowed),
+    /// A pending follow was retried
+    Refollowed(Refollowed),
+    /// A populater run completed
+    Run(Run),
+}
+
+/// A community that moco followed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Followed {
+    /// The community's name in the form `name@instance`
+    pub name: String,
+    /// The federated actor_id
+    pub actor_id: Url,
+    /// The peer the community was discovered on
+    pub peer: String,
+    /// The kind of source the community was discovered from
+    pub source: String,
+    /// The method used to sort the source, unset if the community was followed manually
+    pub sort: Option<SortType>,
+    /// When the community was followed
+    pub at: DateTime<Utc>,
+}
+
+/// A community that moco unfollowed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Unfollowed {
+    /// The community's name in the form `name@instance`
+    pub name: String,
+    /// The federated actor_id
+    pub actor_id: Url,
+    /// Why the community was unfollowed
+    pub reason: String,
+    /// When the community was unfollowed
+    pub at: DateTime<Utc>,
+}
+
+/// A pending follow that moco retried
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Refollowed {
+    /// The community's name in the form `name@instance`
+    pub name: String,
+    /// The federated actor_id
+    pub actor_id: Url,
+    /// How many times the follow has been retried
+    pub attempt: u32,
+    /// When the follow was retried
+    pub at: DateTime<Utc>,
+}
+
+/// The outcome of a single populater run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Run {
+    /// The peer that was populated from
+    pub peer: String,
+    /// The kind of source used
+    pub source: String,
+    /// The method used to sort the source
+    pub sort: SortType,
+    /// When the run started
+    pub started_at: DateTime<Utc>,
+    /// When the run finished
+    pub finished_at: DateTime<Utc>,
+    /// The number of communities returned by the source
+    pub discovered: usize,
+    /// The number of communities that were followed
+    pub followed: usize,
+    /// The number of communities that were skipped
+    pub skipped: usize,
+    /// The number of communities that could not be checked
+    pub failed: usize,
+    /// Why the run failed, if it did
+    pub error: Option<String>,
+}
+
+/// Errors that can occur while reading or writing the state journal
+#[derive(Debug)]
+pub enum StateError {
+    /// The journal could not be read or written
+    Io(io::Error),
+    /// A line in the journal could not be parsed
+    Corrupt(usize, serde_json::Error),
+    /// An event could not be serialized
+    Serialize(serde_json::Error),
+}
+
+impl std::error::Error for StateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Corrupt(_, err) => Some(err),
+            Self::Serialize(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(_) => write!(f, "failed to access the state file"),
+            Self::Corrupt(line, _) => write!(f, "invalid entry on line {line} of the state file"),
+            Self::Serialize(_) => write!(f, "failed to serialize event"),
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> StateError {
+        Self::Io(err)
+    }
+}
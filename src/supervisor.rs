@@ -0,0 +1,410 @@
+use crate::{
+    api::{LemmyApi, RetryPolicy, SortType, Timeouts},
+    config::PeerSettings,
+    populater::{self, Command, Context, NsfwPolicy, Shared, Source, Status},
+    state::{Event, Run},
+};
+use chrono::Utc;
+use clap::ValueEnum;
+use eyre::WrapErr;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{info, instrument, warn};
+use url::Url;
+
+/// Manages the running populater tasks, starting and stopping them as the settings change
+pub struct Supervisor {
+    shared: Shared,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    peers: HashMap<String, LemmyApi>,
+    tasks: HashMap<Key, Task>,
+}
+
+/// Uniquely identifies a populater task
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct Key {
+    peer: String,
+    source: Source,
+    sort: SortType,
+}
+
+/// The settings a populater task was started with
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Settings {
+    limit: i32,
+    interval: Duration,
+    nsfw: NsfwPolicy,
+}
+
+/// A running populater task
+struct Task {
+    settings: Settings,
+    commands: mpsc::UnboundedSender<Command>,
+    status: Arc<Mutex<Status>>,
+    handle: JoinHandle<()>,
+}
+
+/// Selects populater tasks, every unset field matches any task
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub peer: Option<String>,
+    pub source: Option<Source>,
+    pub sort: Option<SortType>,
+}
+
+/// Everything needed to follow communities from an instance outside of the supervisor
+pub struct Follower {
+    domain: String,
+    peer: Option<LemmyApi>,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    shared: Shared,
+}
+
+impl Follower {
+    /// Build a context for following communities, connecting to the instance if necessary
+    pub async fn context(self) -> eyre::Result<Context> {
+        let peer = match self.peer {
+            Some(peer) => peer,
+            None => connect(&self.domain, self.retry, self.timeouts).await?,
+        };
+
+        Ok(populater::context(self.shared, peer, NsfwPolicy::Include))
+    }
+}
+
+/// Describes a running populater task
+#[derive(Debug, Serialize)]
+pub struct TaskInfo {
+    peer: String,
+    source: Source,
+    sort: String,
+    #[serde(flatten)]
+    status: Status,
+}
+
+impl Supervisor {
+    /// Create a new supervisor with no running tasks
+    ///
+    /// Peers are connected to using the retry policy and timeouts.
+    pub fn new(shared: Shared, retry: RetryPolicy, timeouts: Timeouts) -> Supervisor {
+        Supervisor {
+            shared,
+            retry,
+            timeouts,
+            peers: HashMap::new(),
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Start, stop, and restart tasks so that they match the settings
+    ///
+    /// Tasks whose settings are unchanged keep running undisturbed, preserving their schedules.
+    /// Changed tasks are stopped, waiting for any in-progress run, and restarted with the new
+    /// settings, staying paused if they were. If a peer cannot be connected to, its tasks are
+    /// skipped and the first error is returned once every other task has been reconciled.
+    #[instrument(name = "Supervisor::reconcile", skip_all)]
+    pub async fn reconcile(&mut self, peers: &[PeerSettings]) -> eyre::Result<()> {
+        let desired = desired(peers);
+
+        let outdated = self
+            .tasks
+            .iter()
+            .filter(|(key, task)| desired.get(key) != Some(&task.settings))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        let mut paused = HashMap::new();
+        let mut stopping = Vec::new();
+        for key in outdated {
+            let task = self.tasks.remove(&key).expect("task must exist");
+            info!(peer = %key.peer, source = ?key.source, sort = ?key.sort, "stopping populater");
+
+            paused.insert(
+                key,
+                task.status.lock().expect("status lock poisoned").paused,
+            );
+            stopping.push(task.stop());
+        }
+
+        // Let any in-progress runs complete so their replacements don't run alongside them
+        futures::future::join_all(stopping).await;
+
+        self.peers
+            .retain(|domain, _| peers.iter().any(|s| &s.domain == domain));
+
+        let mut result = Ok(());
+        for (key, settings) in desired {
+            if self.tasks.contains_key(&key) {
+                continue;
+            }
+
+            let peer = match self.peer(&key.peer).await {
+                Ok(peer) => peer,
+                Err(error) => {
+                    if result.is_ok() {
+                        result = Err(error);
+                    }
+                    continue;
+                }
+            };
+            if !supports(&peer, &key) {
+                continue;
+            }
+
+            let (commands, receiver) = mpsc::unbounded_channel();
+            let status = Arc::new(Mutex::new(Status {
+                paused: paused.get(&key).copied().unwrap_or_default(),
+                ..Status::default()
+            }));
+            let handle = tokio::task::spawn(key.source.launch(
+                self.context(peer, settings.nsfw),
+                key.sort,
+                settings.limit,
+                settings.interval,
+                receiver,
+                status.clone(),
+            ));
+
+            self.tasks.insert(
+                key,
+                Task {
+                    settings,
+                    commands,
+                    status,
+                    handle,
+                },
+            );
+        }
+
+        result
+    }
+
+    /// Run every populater once, waiting for them all to complete
+    ///
+    /// Peers that cannot be reached are reported as failed runs rather than aborting the others.
+    #[instrument(name = "Supervisor::run_once", skip_all)]
+    pub async fn run_once(&mut self, peers: &[PeerSettings]) -> Vec<Run> {
+        let mut runs = Vec::new();
+        let mut handles = Vec::new();
+        let mut unreachable = HashMap::new();
+        for (key, settings) in desired(peers) {
+            let peer = match unreachable.get(&key.peer) {
+                Some(error) => Err(String::clone(error)),
+                None => self.peer(&key.peer).await.map_err(|error| {
+                    warn!(peer = %key.peer, ?error, "skipping unreachable peer");
+                    format!("{error}: {}", error.root_cause())
+                }),
+            };
+            let peer = match peer {
+                Ok(peer) => peer,
+                Err(error) => {
+                    runs.push(self.unreachable(&key, error.clone()));
+                    unreachable.insert(key.peer, error);
+                    continue;
+                }
+            };
+            if !supports(&peer, &key) {
+                continue;
+            }
+
+            let context = self.context(peer, settings.nsfw);
+            handles.push(tokio::task::spawn(async move {
+                key.source.run(&context, key.sort, settings.limit).await
+            }));
+        }
+
+        for run in futures::future::join_all(handles).await {
+            match run {
+                Ok(run) => runs.push(run),
+                Err(error) => warn!(?error, "populater panicked"),
+            }
+        }
+
+        runs
+    }
+
+    /// Describe the running tasks matching the filter
+    pub fn tasks(&self, filter: &Filter) -> Vec<TaskInfo> {
+        let mut tasks = self
+            .tasks
+            .iter()
+            .filter(|(key, _)| filter.matches(key))
+            .map(|(key, task)| TaskInfo {
+                peer: key.peer.clone(),
+                source: key.source,
+                sort: sort_name(key.sort),
+                status: task.status.lock().expect("status lock poisoned").clone(),
+            })
+            .collect::<Vec<_>>();
+
+        tasks.sort_by(|a, b| (&a.peer, a.source, &a.sort).cmp(&(&b.peer, b.source, &b.sort)));
+        tasks
+    }
+
+    /// Send the command to the running tasks matching the filter, returning the matched tasks
+    #[instrument(name = "Supervisor::command", skip(self))]
+    pub fn command(&self, filter: &Filter, command: Command) -> Vec<TaskInfo> {
+        for (key, task) in &self.tasks {
+            if filter.matches(key) {
+                info!(peer = %key.peer, source = ?key.source, sort = ?key.sort, "sending command");
+                if task.commands.send(command).is_err() {
+                    continue;
+                }
+
+                // Update the status immediately so it's reflected in the response
+                let mut status = task.status.lock().expect("status lock poisoned");
+                match command {
+                    Command::Pause => status.paused = true,
+                    Command::Resume => status.paused = false,
+                    Command::Run | Command::Stop => {}
+                }
+            }
+        }
+
+        self.tasks(filter)
+    }
+
+    /// Prepare to follow communities from the instance, reusing the connection to it if it's a peer
+    pub fn follower(&self, instance: &str) -> Follower {
+        Follower {
+            domain: instance.to_owned(),
+            peer: self.peers.get(instance).cloned(),
+            retry: self.retry,
+            timeouts: self.timeouts,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Stop all the running tasks
+    pub async fn shutdown(self) {
+        futures::future::join_all(self.tasks.into_values().map(Task::stop)).await;
+    }
+
+    /// Get the client for the peer, connecting to it if necessary
+    async fn peer(&mut self, domain: &str) -> eyre::Result<LemmyApi> {
+        if let Some(peer) = self.peers.get(domain) {
+            return Ok(peer.clone());
+        }
+
+        let peer = connect(domain, self.retry, self.timeouts).await?;
+        self.peers.insert(domain.to_owned(), peer.clone());
+        Ok(peer)
+    }
+
+    /// Record that the populater could not run because its peer is unreachable
+    fn unreachable(&self, key: &Key, error: String) -> Run {
+        let now = Utc::now();
+        let run = Run {
+            peer: key.peer.clone(),
+            source: key.source.kind().to_owned(),
+            sort: key.sort,
+            started_at: now,
+            finished_at: now,
+            discovered: 0,
+            followed: 0,
+            skipped: 0,
+            failed: 0,
+            error: Some(error),
+        };
+
+        if self.shared.plan.is_none() {
+            self.shared.state.record_or_warn(Event::Run(run.clone()));
+        }
+        run
+    }
+
+    /// Build the populater context for the peer
+    fn context(&self, peer: LemmyApi, nsfw: NsfwPolicy) -> Context {
+        populater::context(self.shared.clone(), peer, nsfw)
+    }
+}
+
+/// Connect to the peer using the retry policy and timeouts
+async fn connect(domain: &str, retry: RetryPolicy, timeouts: Timeouts) -> eyre::Result<LemmyApi> {
+    let url = Url::parse(&format!("https://{domain}"))
+        .wrap_err_with(|| format!("could not build URL for {domain}"))?;
+    let peer = LemmyApi::connect(&url, timeouts)
+        .await
+        .wrap_err_with(|| format!("cannot connect to peer {domain}"))?
+        .with_retry_policy(retry);
+
+    Ok(peer)
+}
+
+/// Check that the peer supports the task's sort method, warning if it doesn't
+fn supports(peer: &LemmyApi, key: &Key) -> bool {
+    let supported = peer.supports_sort(key.sort);
+    if !supported {
+        warn!(
+            peer = %key.peer,
+            source = ?key.source,
+            sort = ?key.sort,
+            version = %peer.version(),
+            "sort method not supported by peer, skipping"
+        );
+    }
+
+    supported
+}
+
+/// Compute the tasks that should be running for the peers
+fn desired(peers: &[PeerSettings]) -> HashMap<Key, Settings> {
+    let mut desired = HashMap::new();
+    for peer in peers {
+        for source in &peer.sources {
+            // Trending ignores the sort methods, so one populater is enough
+            let sorts = match source {
+                Source::Trending => &[populater::TRENDING_SORT][..],
+                _ => &peer.sort_methods,
+            };
+
+            for sort in sorts {
+                let key = Key {
+                    peer: peer.domain.clone(),
+                    source: *source,
+                    sort: *sort,
+                };
+                let settings = Settings {
+                    limit: peer.limit(*source),
+                    interval: peer.run_interval,
+                    nsfw: peer.nsfw,
+                };
+                desired.insert(key, settings);
+            }
+        }
+    }
+
+    desired
+}
+
+impl Filter {
+    /// Check whether the task is selected
+    fn matches(&self, key: &Key) -> bool {
+        self.peer.as_ref().is_none_or(|peer| peer == &key.peer)
+            && self.source.is_none_or(|source| source == key.source)
+            && self.sort.is_none_or(|sort| sort == key.sort)
+    }
+}
+
+/// The name of the sort method as used on the command line
+fn sort_name(sort: SortType) -> String {
+    sort.to_possible_value()
+        .expect("sort methods must not be skipped")
+        .get_name()
+        .to_owned()
+}
+
+impl Task {
+    /// Signal the task to stop and wait for it to exit
+    async fn stop(self) {
+        // The task may have already exited, in which case there's nothing to signal
+        let _ = self.commands.send(Command::Stop);
+        let _ = self.handle.await;
+    }
+}
@@ -0,0 +1,8 @@
+/// Compute the width of a column from its header and values
+pub fn column_width<'a>(header: &str, values: impl Iterator<Item = &'a str>) -> usize {
+    values
+        .map(str::len)
+        .chain([header.len()])
+        .max()
+        .unwrap_or(0)
+}